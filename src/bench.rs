@@ -0,0 +1,88 @@
+use std::time::{Duration, Instant};
+
+use cgmath::vec2;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::{
+    gen::{self, Info, Terrain},
+    tree::Facing,
+};
+
+/// Deepest level the benchmark quads are picked from.
+const MAX_DEPTH: u32 = 16;
+
+/// Generates the grids of `count` quads at random places and depths, once a
+/// vertex at a time and once in batches, and compares the timings. Returns
+/// whether both gave bit for bit the same heights and normals.
+pub fn noise(terrain: &Terrain, count: u32) -> bool {
+    let mut rng = SmallRng::seed_from_u64(0);
+    let quads: Vec<_> = (0..count)
+        .map(|i| {
+            let depth = i % (MAX_DEPTH + 1);
+            let cells = 1 << depth;
+            let scale = 1.0 / cells as f32;
+            let mut cell = || (rng.gen::<f32>() * cells as f32) as u32 as f32;
+            let offset = vec2(cell(), cell()) * scale * 2.0 - vec2(1.0, 1.0) + vec2(scale, scale);
+
+            Info {
+                facing: Facing::all()[i as usize % 6],
+                scale,
+                offset,
+            }
+        })
+        .collect();
+
+    let mut per_vertex = Duration::ZERO;
+    let mut batched = Duration::ZERO;
+    let mut mismatches = 0;
+
+    for info in &quads {
+        let spacing = info.spacing();
+        let directions: Vec<_> = gen::grid_directions(info).collect();
+
+        let start = Instant::now();
+        let elevations: Vec<_> = directions
+            .iter()
+            .map(|&direction| terrain.elevation(direction, spacing))
+            .collect();
+        let normals: Vec<_> = directions
+            .iter()
+            .map(|&direction| terrain.normal(direction, spacing))
+            .collect();
+        per_vertex += start.elapsed();
+
+        let start = Instant::now();
+        let batch_elevations = terrain.elevations(&directions, spacing);
+        let batch_normals = terrain.normals(&directions, spacing);
+        batched += start.elapsed();
+
+        mismatches += elevations
+            .iter()
+            .zip(&batch_elevations)
+            .filter(|(a, b)| a.to_bits() != b.to_bits())
+            .count();
+        mismatches += normals
+            .iter()
+            .zip(&batch_normals)
+            .filter(|(a, b)| {
+                let (a, b): ([f32; 3], [f32; 3]) = ((**a).into(), (**b).into());
+                a.map(f32::to_bits) != b.map(f32::to_bits)
+            })
+            .count();
+    }
+
+    println!(
+        "{} quads: per vertex {:.1?}, batched {:.1?} ({:.2}x)",
+        quads.len(),
+        per_vertex,
+        batched,
+        per_vertex.as_secs_f64() / batched.as_secs_f64()
+    );
+    if mismatches > 0 {
+        println!("{} samples differ", mismatches);
+    } else {
+        println!("identical results");
+    }
+
+    mismatches == 0
+}
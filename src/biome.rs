@@ -0,0 +1,74 @@
+use cgmath::Vector3;
+
+use crate::{
+    gen::{ElevationSampler, FULL_DETAIL},
+    planet::{Biome, Biomes},
+};
+
+pub const NO_BIOME: u32 = u32::MAX;
+
+pub struct BiomeMap {
+    config: Biomes,
+    moisture: ElevationSampler,
+    temperature_range: f32,
+}
+
+impl BiomeMap {
+    pub fn new(config: &Biomes, seed: u32) -> Self {
+        let noise = &config.moisture;
+        let (min, max) = config
+            .table
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), biome| {
+                (min.min(biome.temperature), max.max(biome.temperature))
+            });
+
+        Self {
+            config: config.clone(),
+            moisture: ElevationSampler::new(
+                seed,
+                noise.octaves,
+                noise.frequency,
+                noise.lacunarity,
+                noise.amplitude,
+                noise.persistence,
+                false,
+            ),
+            temperature_range: (max - min).max(f32::EPSILON),
+        }
+    }
+
+    pub fn temperature(&self, direction: Vector3<f32>, height: f32) -> f32 {
+        let latitude = direction.y.abs().asin() / std::f32::consts::FRAC_PI_2;
+        let sea_level = self.config.equator_temperature
+            + (self.config.pole_temperature - self.config.equator_temperature) * latitude;
+        sea_level - self.config.lapse_rate * height.max(0.0)
+    }
+
+    pub fn moisture(&self, direction: Vector3<f32>) -> f32 {
+        (self.moisture.sample(direction.cast().unwrap(), FULL_DETAIL) * 0.5 + 0.5).clamp(0.0, 1.0)
+    }
+
+    /// Picks the biome whose ideal temperature and moisture are closest.
+    pub fn classify(&self, direction: Vector3<f32>, height: f32) -> u32 {
+        let temperature = self.temperature(direction, height);
+        let moisture = self.moisture(direction);
+
+        self.config
+            .table
+            .iter()
+            .enumerate()
+            .map(|(i, biome)| {
+                let dt = (biome.temperature - temperature) / self.temperature_range;
+                let dm = biome.moisture - moisture;
+                (i as u32, dt * dt + dm * dm)
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(i, _)| i)
+            .unwrap_or(NO_BIOME)
+    }
+
+    pub fn biome(&self, id: u32) -> Option<&Biome> {
+        self.config.table.get(id as usize)
+    }
+}
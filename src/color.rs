@@ -0,0 +1,70 @@
+use cgmath::{Vector3, Zero};
+
+use crate::planet::ColorRule;
+
+pub struct SurfacePoint {
+    /// Height above the planet radius.
+    pub height: f32,
+    /// 0 on flat ground, 1 on a vertical cliff.
+    pub slope: f32,
+    /// Signed latitude in degrees.
+    pub latitude: f32,
+}
+
+pub fn evaluate(base: Vector3<f32>, rules: &[ColorRule], point: &SurfacePoint) -> Vector3<f32> {
+    rules
+        .iter()
+        .fold(base, |color, rule| rule.apply(color, point))
+}
+
+impl ColorRule {
+    fn apply(&self, below: Vector3<f32>, point: &SurfacePoint) -> Vector3<f32> {
+        match self {
+            Self::Ramp(stops) => ramp(stops, point.height),
+            Self::Elevation { from, to, color } => {
+                blend(below, *color, smoothstep(*from, *to, point.height))
+            }
+            Self::Slope { from, to, color } => {
+                blend(below, *color, smoothstep(*from, *to, point.slope))
+            }
+            Self::Latitude { from, to, color } => {
+                blend(below, *color, smoothstep(*from, *to, point.latitude.abs()))
+            }
+        }
+    }
+}
+
+fn ramp(stops: &[(f32, [f32; 3])], value: f32) -> Vector3<f32> {
+    let (first, last) = match (stops.first(), stops.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Vector3::zero(),
+    };
+
+    if value <= first.0 {
+        return first.1.into();
+    }
+
+    for pair in stops.windows(2) {
+        let ((h0, c0), (h1, c1)) = (pair[0], pair[1]);
+        if value <= h1 {
+            let t = (value - h0) / (h1 - h0).max(f32::EPSILON);
+            return blend(c0.into(), c1, t);
+        }
+    }
+
+    last.1.into()
+}
+
+fn blend(below: Vector3<f32>, color: [f32; 3], t: f32) -> Vector3<f32> {
+    below + (Vector3::from(color) - below) * t
+}
+
+/// A hard step at `to` when the range is empty.
+fn smoothstep(from: f32, to: f32, value: f32) -> f32 {
+    if from == to {
+        return if value >= to { 1.0 } else { 0.0 };
+    }
+
+    let t = ((value - from) / (to - from)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
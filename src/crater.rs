@@ -0,0 +1,158 @@
+use cgmath::{InnerSpace, Vector3};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::gen::random_direction;
+
+/// Cells along the edge of each cube face in the lookup grid.
+const CELLS: usize = 32;
+/// How far the rim reaches past the crater radius, relative to it.
+const REACH: f32 = 1.5;
+
+pub struct CraterField {
+    craters: Vec<Crater>,
+    /// Indices into `craters` for every cell of a cube-mapped grid.
+    cells: Vec<Vec<u32>>,
+}
+
+struct Crater {
+    center: Vector3<f32>,
+    radius: f32,
+    depth: f32,
+    rim: f32,
+}
+
+impl CraterField {
+    pub fn new(
+        seed: u32,
+        count: u32,
+        min_radius: f32,
+        max_radius: f32,
+        exponent: f32,
+        depth: f32,
+        rim: f32,
+    ) -> Self {
+        let mut rng = SmallRng::seed_from_u64(seed as u64);
+
+        let craters: Vec<_> = (0..count)
+            .map(|_| {
+                let radius = power_law(rng.gen(), min_radius, max_radius, exponent);
+
+                Crater {
+                    center: random_direction(&mut rng),
+                    radius,
+                    depth: radius * depth,
+                    rim: radius * rim,
+                }
+            })
+            .collect();
+
+        let mut cells = vec![Vec::new(); 6 * CELLS * CELLS];
+        for (i, crater) in craters.iter().enumerate() {
+            for cell in crater.cells() {
+                if cells[cell].last() != Some(&(i as u32)) {
+                    cells[cell].push(i as u32);
+                }
+            }
+        }
+
+        Self { craters, cells }
+    }
+
+    pub fn sample(&self, direction: Vector3<f32>) -> f32 {
+        self.cells[cell(direction)]
+            .iter()
+            .map(|&i| self.craters[i as usize].sample(direction))
+            .sum()
+    }
+}
+
+impl Crater {
+    fn sample(&self, direction: Vector3<f32>) -> f32 {
+        let distance = self.center.dot(direction).clamp(-1.0, 1.0).acos() / self.radius;
+        if distance >= REACH {
+            return 0.0;
+        }
+
+        let bowl = (distance * distance - 1.0).min(0.0) * self.depth;
+        let rim = {
+            let t = ((distance - 1.0) / (REACH - 1.0)).abs().min(1.0);
+            let falloff = 1.0 - t * t;
+            falloff * falloff * self.rim
+        };
+
+        bowl + rim
+    }
+
+    /// Every grid cell the crater reaches, with duplicates next to each other.
+    fn cells(&self) -> Vec<usize> {
+        // Sample the reach of the crater densely enough that no cell is skipped.
+        let spacing = 0.25 / CELLS as f32;
+        let angle = self.radius * REACH + spacing * 2.0;
+        if angle >= 1.2 {
+            return (0..6 * CELLS * CELLS).collect();
+        }
+
+        // Projected onto the tangent plane at the center.
+        let reach = angle.tan();
+        let steps = (reach / spacing).ceil() as i32;
+
+        let tangent = if self.center.x.abs() < 0.9 {
+            Vector3::unit_x()
+        } else {
+            Vector3::unit_y()
+        };
+        let u = self.center.cross(tangent).normalize();
+        let v = self.center.cross(u);
+
+        let mut cells = Vec::new();
+        for y in -steps..=steps {
+            for x in -steps..=steps {
+                let (dx, dy) = (x as f32 * spacing, y as f32 * spacing);
+                if dx * dx + dy * dy <= reach * reach {
+                    let direction = (self.center + u * dx + v * dy).normalize();
+                    cells.push(cell(direction));
+                }
+            }
+        }
+
+        cells.sort_unstable();
+        cells
+    }
+}
+
+/// Index of the lookup cell containing a unit direction.
+fn cell(direction: Vector3<f32>) -> usize {
+    let abs = direction.map(f32::abs);
+    let (face, u, v, major) = if abs.x >= abs.y && abs.x >= abs.z {
+        (
+            usize::from(direction.x < 0.0),
+            direction.y,
+            direction.z,
+            abs.x,
+        )
+    } else if abs.y >= abs.z {
+        (
+            2 + usize::from(direction.y < 0.0),
+            direction.x,
+            direction.z,
+            abs.y,
+        )
+    } else {
+        (
+            4 + usize::from(direction.z < 0.0),
+            direction.x,
+            direction.y,
+            abs.z,
+        )
+    };
+
+    let to_cell =
+        |e: f32| (((e / major) * 0.5 + 0.5) * CELLS as f32).min(CELLS as f32 - 1.0) as usize;
+    (face * CELLS + to_cell(v)) * CELLS + to_cell(u)
+}
+
+/// Samples a radius from a truncated power-law distribution.
+fn power_law(t: f32, min: f32, max: f32, exponent: f32) -> f32 {
+    let (a, b) = (min.powf(-exponent), max.powf(-exponent));
+    (a - t * (a - b)).powf(-1.0 / exponent)
+}
@@ -0,0 +1,130 @@
+use cgmath::{vec2, InnerSpace, Vector2, Vector3};
+
+use crate::tree::Facing;
+
+/// Values on a grid of vertices over each face of the cube, with the
+/// vertices on shared edges and corners present on every face they touch.
+#[derive(Clone)]
+pub struct CubeMap {
+    resolution: usize,
+    faces: [Vec<f32>; 6],
+}
+
+impl CubeMap {
+    /// `resolution` is the number of cells along a face edge.
+    pub fn from_fn(resolution: usize, f: impl Fn(Vector3<f32>) -> f32) -> Self {
+        let faces = Facing::all().map(|facing| {
+            let mut face = Vec::with_capacity((resolution + 1) * (resolution + 1));
+            for y in 0..=resolution {
+                for x in 0..=resolution {
+                    let uv = texel_to_uv(resolution, vec2(x as f32, y as f32));
+                    face.push(f(facing.orient(uv).normalize()));
+                }
+            }
+            face
+        });
+
+        Self { resolution, faces }
+    }
+
+    pub fn resolution(&self) -> usize {
+        self.resolution
+    }
+
+    pub fn get(&self, facing: Facing, x: usize, y: usize) -> f32 {
+        self.faces[facing as usize][y * (self.resolution + 1) + x]
+    }
+
+    pub fn get_mut(&mut self, facing: Facing, x: usize, y: usize) -> &mut f32 {
+        &mut self.faces[facing as usize][y * (self.resolution + 1) + x]
+    }
+
+    /// Continuous texel coordinates of a direction on the face it points through.
+    pub fn locate(&self, dir: Vector3<f32>) -> (Facing, Vector2<f32>) {
+        let facing = Facing::of(dir);
+        let uv = facing.project(dir).unwrap();
+        (facing, self.to_texel(uv))
+    }
+
+    pub fn to_texel(&self, uv: Vector2<f32>) -> Vector2<f32> {
+        (uv + vec2(1.0, 1.0)) * 0.5 * self.resolution as f32
+    }
+
+    pub fn to_uv(&self, texel: Vector2<f32>) -> Vector2<f32> {
+        texel_to_uv(self.resolution, texel)
+    }
+
+    /// Bilinear sample in the direction, which is continuous across faces.
+    pub fn sample(&self, dir: Vector3<f32>) -> f32 {
+        let (facing, texel) = self.locate(dir);
+        self.sample_texel(facing, texel)
+    }
+
+    pub fn sample_texel(&self, facing: Facing, texel: Vector2<f32>) -> f32 {
+        let ((x, y), (fx, fy)) = self.cell(texel);
+        let h00 = self.get(facing, x, y);
+        let h10 = self.get(facing, x + 1, y);
+        let h01 = self.get(facing, x, y + 1);
+        let h11 = self.get(facing, x + 1, y + 1);
+
+        let top = h00 + (h10 - h00) * fx;
+        let bottom = h01 + (h11 - h01) * fx;
+        top + (bottom - top) * fy
+    }
+
+    /// The cell containing a texel position and the position within the cell.
+    pub fn cell(&self, texel: Vector2<f32>) -> ((usize, usize), (f32, f32)) {
+        let max = self.resolution as f32;
+        let texel = texel.map(|e| e.clamp(0.0, max));
+        let x = (texel.x.floor() as usize).min(self.resolution - 1);
+        let y = (texel.y.floor() as usize).min(self.resolution - 1);
+        ((x, y), (texel.x - x as f32, texel.y - y as f32))
+    }
+
+    /// Averages the copies of every vertex on a shared edge, so that all
+    /// faces agree on it again after being modified independently.
+    pub fn stitch(&mut self) {
+        let res = self.resolution;
+        let mut groups: Vec<Vec<(Facing, usize, usize)>> = Vec::new();
+
+        for facing in Facing::all() {
+            for y in 0..=res {
+                for x in 0..=res {
+                    if x != 0 && y != 0 && x != res && y != res {
+                        continue;
+                    }
+
+                    let dir = facing.orient(self.to_uv(vec2(x as f32, y as f32)));
+                    let group: Vec<_> = Facing::all()
+                        .into_iter()
+                        .filter_map(|other| {
+                            let texel = self.to_texel(other.project(dir)?);
+                            let (ox, oy) = (texel.x.round(), texel.y.round());
+                            let on_grid =
+                                (texel.x - ox).abs() < 1e-3 && (texel.y - oy).abs() < 1e-3;
+                            let inside = (0.0..=res as f32).contains(&ox)
+                                && (0.0..=res as f32).contains(&oy);
+                            (on_grid && inside).then_some((other, ox as usize, oy as usize))
+                        })
+                        .collect();
+
+                    if group.len() > 1 && group[0].0 as usize == facing as usize {
+                        groups.push(group);
+                    }
+                }
+            }
+        }
+
+        for group in groups {
+            let sum: f32 = group.iter().map(|&(f, x, y)| self.get(f, x, y)).sum();
+            let average = sum / group.len() as f32;
+            for (f, x, y) in group {
+                *self.get_mut(f, x, y) = average;
+            }
+        }
+    }
+}
+
+fn texel_to_uv(resolution: usize, texel: Vector2<f32>) -> Vector2<f32> {
+    texel * (2.0 / resolution as f32) - vec2(1.0, 1.0)
+}
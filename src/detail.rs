@@ -0,0 +1,99 @@
+/// Edge length of the detail textures, in texels.
+pub const SIZE: usize = 256;
+
+/// Tileable grayscale textures for the terrain shader: soft ground first,
+/// then ridged rock. Values average around the middle, so that the shader
+/// can brighten and darken by them.
+pub fn layers() -> [Vec<u8>; 2] {
+    let ground = fractal(0, 8, 6, |value| value);
+    let rock = fractal(1, 4, 7, |value| 1.0 - (value * 2.0 - 1.0).abs());
+    [normalize(ground), normalize(rock)]
+}
+
+/// Each level half the size of the one before, down to a single texel.
+pub fn mip_levels(image: Vec<u8>) -> Vec<Vec<u8>> {
+    let mut levels = vec![image];
+    let mut size = SIZE;
+    while size > 1 {
+        let previous = levels.last().unwrap();
+        let half = size / 2;
+        let level = (0..half * half)
+            .map(|i| {
+                let (x, y) = (i % half * 2, i / half * 2);
+                let sum: u32 = [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)]
+                    .iter()
+                    .map(|&(x, y)| previous[y * size + x] as u32)
+                    .sum();
+                ((sum + 2) / 4) as u8
+            })
+            .collect();
+        levels.push(level);
+        size = half;
+    }
+    levels
+}
+
+/// Octaves of value noise, each with twice the cells of the one before, on
+/// lattices that wrap around the texture.
+fn fractal(seed: u32, cells: usize, octaves: u32, shape: impl Fn(f32) -> f32) -> Vec<f32> {
+    let mut image = vec![0.0; SIZE * SIZE];
+    let mut amplitude = 1.0;
+    for octave in 0..octaves {
+        let cells = (cells << octave).min(SIZE);
+        for (i, texel) in image.iter_mut().enumerate() {
+            let (x, y) = ((i % SIZE) as f32, (i / SIZE) as f32);
+            let scale = cells as f32 / SIZE as f32;
+            let value = value_noise(seed.wrapping_add(octave), cells, x * scale, y * scale);
+            *texel += shape(value) * amplitude;
+        }
+        amplitude *= 0.5;
+    }
+    image
+}
+
+fn value_noise(seed: u32, period: usize, x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (smooth(x - x0), smooth(y - y0));
+    let lattice = |dx: usize, dy: usize| {
+        let x = (x0 as usize + dx) % period;
+        let y = (y0 as usize + dy) % period;
+        hash(seed, x as u32, y as u32)
+    };
+
+    let top = lattice(0, 0) + (lattice(1, 0) - lattice(0, 0)) * fx;
+    let bottom = lattice(0, 1) + (lattice(1, 1) - lattice(0, 1)) * fx;
+    top + (bottom - top) * fy
+}
+
+fn smooth(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Between 0 and 1.
+fn hash(seed: u32, x: u32, y: u32) -> f32 {
+    let mut h = seed
+        .wrapping_mul(0x27d4_eb2d)
+        .wrapping_add(x.wrapping_mul(0x85eb_ca6b))
+        .wrapping_add(y.wrapping_mul(0xc2b2_ae35));
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^= h >> 15;
+    h as f32 / u32::MAX as f32
+}
+
+/// Stretches values to the full range, centered on their mean.
+fn normalize(image: Vec<f32>) -> Vec<u8> {
+    let mean = image.iter().sum::<f32>() / image.len() as f32;
+    let spread = image
+        .iter()
+        .map(|value| (value - mean).abs())
+        .fold(0.0, f32::max)
+        .max(f32::EPSILON);
+
+    image
+        .iter()
+        .map(|value| ((0.5 + (value - mean) / spread * 0.5) * 255.0).round() as u8)
+        .collect()
+}
@@ -0,0 +1,137 @@
+use cgmath::{vec2, InnerSpace, Vector2, Vector3};
+use rand::{rngs::SmallRng, SeedableRng};
+
+use crate::{cubemap::CubeMap, gen::random_direction, tree::Facing};
+
+pub struct Erosion {
+    pub droplets: u32,
+    pub lifetime: u32,
+    pub inertia: f32,
+    pub capacity: f32,
+    pub min_slope: f32,
+    pub erode: f32,
+    pub deposit: f32,
+    pub evaporate: f32,
+    pub gravity: f32,
+}
+
+/// Simulates water droplets flowing over the heightfield, carrying sediment
+/// from steep slopes to where they slow down. Droplets start all over the
+/// sphere and move on to the neighbouring face when they cross an edge.
+pub fn erode(map: &mut CubeMap, seed: u32, params: &Erosion) {
+    let mut rng = SmallRng::seed_from_u64(seed as u64);
+
+    // Heights are handled in texels, so that slopes don't depend on resolution.
+    let texel_scale = map.resolution() as f32 * 0.5;
+
+    for _ in 0..params.droplets {
+        let start = random_direction(&mut rng);
+        let (mut facing, mut pos) = map.locate(start);
+        let mut dir = vec2(0.0, 0.0);
+        let mut speed = 1.0;
+        let mut water = 1.0;
+        let mut sediment = 0.0;
+
+        for _ in 0..params.lifetime {
+            let (height, gradient) = height_gradient(map, facing, pos);
+            let (height, gradient) = (height * texel_scale, gradient * texel_scale);
+
+            dir = dir * params.inertia - gradient * (1.0 - params.inertia);
+            if dir.magnitude2() < 1e-12 {
+                break;
+            }
+            dir = dir.normalize();
+
+            let (new_facing, new_pos, new_dir) = step(map, facing, pos, dir);
+            let new_height = map.sample_texel(new_facing, new_pos) * texel_scale;
+            let delta = new_height - height;
+
+            let capacity = (-delta).max(params.min_slope) * speed * water * params.capacity;
+            let change = if sediment > capacity || delta > 0.0 {
+                let amount = if delta > 0.0 {
+                    delta.min(sediment)
+                } else {
+                    (sediment - capacity) * params.deposit
+                };
+                sediment -= amount;
+                amount
+            } else {
+                let amount = ((capacity - sediment) * params.erode).min(-delta);
+                sediment += amount;
+                -amount
+            };
+            splat(map, facing, pos, change / texel_scale);
+
+            speed = (speed * speed - delta * params.gravity).max(0.0).sqrt();
+            water *= 1.0 - params.evaporate;
+
+            facing = new_facing;
+            pos = new_pos;
+            dir = new_dir;
+        }
+    }
+
+    map.stitch();
+}
+
+/// Moves one texel along `dir`, crossing over to the neighbouring face if needed.
+fn step(
+    map: &CubeMap,
+    facing: Facing,
+    pos: Vector2<f32>,
+    dir: Vector2<f32>,
+) -> (Facing, Vector2<f32>, Vector2<f32>) {
+    let next = pos + dir;
+    let max = map.resolution() as f32;
+    if (0.0..=max).contains(&next.x) && (0.0..=max).contains(&next.y) {
+        return (facing, next, dir);
+    }
+
+    let from = facing.orient(map.to_uv(pos));
+    let to = facing.orient(map.to_uv(next));
+    let new_facing = Facing::of(to);
+    let project = |point: Vector3<f32>| {
+        new_facing
+            .project(point)
+            .map(|uv| map.to_texel(uv))
+            .unwrap_or(vec2(max * 0.5, max * 0.5))
+    };
+
+    let new_pos = project(to);
+    let new_dir = new_pos - project(from);
+    let new_dir = if new_dir.magnitude2() > 1e-12 {
+        new_dir.normalize()
+    } else {
+        dir
+    };
+
+    (new_facing, new_pos, new_dir)
+}
+
+fn height_gradient(map: &CubeMap, facing: Facing, pos: Vector2<f32>) -> (f32, Vector2<f32>) {
+    let ((x, y), (fx, fy)) = map.cell(pos);
+    let h00 = map.get(facing, x, y);
+    let h10 = map.get(facing, x + 1, y);
+    let h01 = map.get(facing, x, y + 1);
+    let h11 = map.get(facing, x + 1, y + 1);
+
+    let gradient = vec2(
+        (h10 - h00) * (1.0 - fy) + (h11 - h01) * fy,
+        (h01 - h00) * (1.0 - fx) + (h11 - h10) * fx,
+    );
+    let height = h00 * (1.0 - fx) * (1.0 - fy)
+        + h10 * fx * (1.0 - fy)
+        + h01 * (1.0 - fx) * fy
+        + h11 * fx * fy;
+
+    (height, gradient)
+}
+
+/// Spreads a height change over the corners of the cell, weighted bilinearly.
+fn splat(map: &mut CubeMap, facing: Facing, pos: Vector2<f32>, change: f32) {
+    let ((x, y), (fx, fy)) = map.cell(pos);
+    *map.get_mut(facing, x, y) += change * (1.0 - fx) * (1.0 - fy);
+    *map.get_mut(facing, x + 1, y) += change * fx * (1.0 - fy);
+    *map.get_mut(facing, x, y + 1) += change * (1.0 - fx) * fy;
+    *map.get_mut(facing, x + 1, y + 1) += change * fx * fy;
+}
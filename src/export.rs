@@ -0,0 +1,342 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use cgmath::{vec2, Array, InnerSpace, Vector3};
+use png::{BitDepth, ColorType};
+use rayon::prelude::*;
+
+use crate::{gen, gen::Terrain, render::Vertex, tree::Facing};
+
+#[derive(Clone, Copy)]
+pub enum HeightFormat {
+    /// Grayscale, spanning the lowest to the highest point on the planet.
+    Png16,
+    /// Little-endian heights above the radius, as sampled.
+    RawF32,
+}
+
+/// Writes the height of the planet as an equirectangular image of
+/// `2 * resolution` by `resolution` pixels, and six cube faces of
+/// `resolution` squared. Both map the same way the heightmap layer reads
+/// them back.
+pub fn heightmap(
+    terrain: &Terrain,
+    dir: &Path,
+    resolution: u32,
+    format: HeightFormat,
+) -> io::Result<()> {
+    if resolution == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "resolution must be at least 1",
+        ));
+    }
+    fs::create_dir_all(dir)?;
+    let n = resolution as usize;
+
+    let mut images = Vec::with_capacity(7);
+
+    // With the detail of the quads that would show the cube faces at this
+    // resolution, so the images match the terrain as rendered.
+    let spacing = gen::Info::spacing_for(resolution);
+    let equirectangular: Vec<f32> = (0..n)
+        .flat_map(|y| (0..n * 2).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let latitude = 90.0 - (y as f32 + 0.5) / n as f32 * 180.0;
+            let longitude = (x as f32 + 0.5) / (n * 2) as f32 * 360.0 - 180.0;
+            terrain.height(gen::direction(latitude, longitude), spacing)
+        })
+        .collect();
+    images.push(("equirectangular", n * 2, n, equirectangular));
+
+    for facing in Facing::all() {
+        let face = (0..n)
+            .flat_map(|y| (0..n).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let uv = vec2(x as f32 + 0.5, y as f32 + 0.5) * (2.0 / n as f32) - vec2(1.0, 1.0);
+                terrain.height(facing.orient(uv).normalize(), spacing)
+            })
+            .collect();
+        images.push((face_name(facing), n, n, face));
+    }
+
+    let (min, max) = images
+        .iter()
+        .flat_map(|(_, _, _, data)| data)
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &e| {
+            (min.min(e), max.max(e))
+        });
+
+    for (name, width, height, data) in &images {
+        match format {
+            HeightFormat::Png16 => {
+                let range = (max - min).max(f32::EPSILON);
+                let samples: Vec<u16> = data
+                    .iter()
+                    .map(|e| ((e - min) / range * u16::MAX as f32).round() as u16)
+                    .collect();
+                write_png(
+                    &dir.join(format!("{}.png", name)),
+                    *width,
+                    *height,
+                    &samples,
+                )?;
+            }
+            HeightFormat::RawF32 => {
+                let mut file = BufWriter::new(File::create(dir.join(format!("{}.raw", name)))?);
+                for e in data {
+                    file.write_all(&e.to_le_bytes())?;
+                }
+                file.flush()?;
+            }
+        }
+    }
+
+    println!("height from {} to {}", min, max);
+    Ok(())
+}
+
+fn write_png(path: &Path, width: usize, height: usize, samples: &[u16]) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(ColorType::Grayscale);
+    encoder.set_depth(BitDepth::Sixteen);
+
+    let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_be_bytes()).collect();
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&bytes).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+fn face_name(facing: Facing) -> &'static str {
+    match facing {
+        Facing::North => "north",
+        Facing::South => "south",
+        Facing::East => "east",
+        Facing::West => "west",
+        Facing::Up => "up",
+        Facing::Down => "down",
+    }
+}
+
+/// Writes the terrain of the given quads as one mesh, in the format given
+/// by the extension of `path`. Vertices on shared quad edges stay separate,
+/// and each quad keeps the skirts that cover cracks between depths.
+pub fn mesh(terrain: &Terrain, quads: &[gen::Info], path: &Path) -> io::Result<()> {
+    let format = match path.extension().and_then(|e| e.to_str()) {
+        Some("obj") => MeshFormat::Obj,
+        Some("ply") => MeshFormat::Ply,
+        Some("glb") => MeshFormat::Glb,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "expected a .obj, .ply or .glb file",
+            ))
+        }
+    };
+
+    let meshes: Vec<_> = quads
+        .par_iter()
+        .map(|info| gen::quad_data(info, terrain).mesh)
+        .collect();
+
+    let mut vertices = Vec::new();
+    let mut triangles = Vec::new();
+    for data in meshes {
+        let base = vertices.len() as u32;
+        triangles.extend(
+            data.triangles
+                .iter()
+                .map(|triangle| triangle.map(|i| base + i)),
+        );
+        vertices.extend(data.vertices.into_iter().map(|mut vertex| {
+            let position = data.origin + vertex.position.cast().unwrap();
+            vertex.position = position.cast().unwrap();
+            vertex
+        }));
+    }
+
+    let mut file = BufWriter::new(File::create(path)?);
+    match format {
+        MeshFormat::Obj => write_obj(&mut file, &vertices, &triangles)?,
+        MeshFormat::Ply => write_ply(&mut file, &vertices, &triangles)?,
+        MeshFormat::Glb => write_glb(&mut file, &vertices, &triangles)?,
+    }
+    file.flush()?;
+
+    println!(
+        "{}: {} quads, {} vertices, {} triangles",
+        path.display(),
+        quads.len(),
+        vertices.len(),
+        triangles.len()
+    );
+    Ok(())
+}
+
+enum MeshFormat {
+    Obj,
+    Ply,
+    Glb,
+}
+
+/// With vertex colors after the position, in sRGB.
+fn write_obj(w: &mut impl Write, vertices: &[Vertex], triangles: &[[u32; 3]]) -> io::Result<()> {
+    for vertex in vertices {
+        let (p, c) = (vertex.position, vertex.color.map(linear_to_srgb));
+        writeln!(w, "v {} {} {} {} {} {}", p.x, p.y, p.z, c.x, c.y, c.z)?;
+    }
+    for vertex in vertices {
+        let n = vertex.normal;
+        writeln!(w, "vn {} {} {}", n.x, n.y, n.z)?;
+    }
+    for triangle in triangles {
+        let [a, b, c] = triangle.map(|i| i + 1);
+        writeln!(w, "f {0}//{0} {1}//{1} {2}//{2}", a, b, c)?;
+    }
+    Ok(())
+}
+
+fn write_ply(w: &mut impl Write, vertices: &[Vertex], triangles: &[[u32; 3]]) -> io::Result<()> {
+    writeln!(w, "ply")?;
+    writeln!(w, "format binary_little_endian 1.0")?;
+    writeln!(w, "element vertex {}", vertices.len())?;
+    for property in ["x", "y", "z", "nx", "ny", "nz"] {
+        writeln!(w, "property float {}", property)?;
+    }
+    for property in ["red", "green", "blue"] {
+        writeln!(w, "property uchar {}", property)?;
+    }
+    writeln!(w, "element face {}", triangles.len())?;
+    writeln!(w, "property list uchar uint vertex_indices")?;
+    writeln!(w, "end_header")?;
+
+    for vertex in vertices {
+        for e in [vertex.position, vertex.normal]
+            .iter()
+            .flat_map(|v| [v.x, v.y, v.z])
+        {
+            w.write_all(&e.to_le_bytes())?;
+        }
+        let color: [f32; 3] = vertex.color.map(linear_to_srgb).into();
+        w.write_all(&color.map(|e| (e.clamp(0.0, 1.0) * 255.0).round() as u8))?;
+    }
+    for triangle in triangles {
+        w.write_all(&[3])?;
+        for i in triangle {
+            w.write_all(&i.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Binary glTF with positions, normals, linear vertex colors and indices.
+fn write_glb(w: &mut impl Write, vertices: &[Vertex], triangles: &[[u32; 3]]) -> io::Result<()> {
+    const ARRAY_BUFFER: u32 = 34962;
+    const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+    const FLOAT: u32 = 5126;
+    const UNSIGNED_INT: u32 = 5125;
+
+    let mut bin = Vec::new();
+    let mut views = Vec::new();
+    let attributes: [fn(&Vertex) -> Vector3<f32>; 3] = [|v| v.position, |v| v.normal, |v| v.color];
+    for attribute in attributes {
+        let offset = bin.len();
+        for vertex in vertices {
+            let v = attribute(vertex);
+            for e in [v.x, v.y, v.z] {
+                bin.extend(e.to_le_bytes());
+            }
+        }
+        views.push((offset, bin.len() - offset, ARRAY_BUFFER));
+    }
+    let offset = bin.len();
+    for i in triangles.iter().flatten() {
+        bin.extend(i.to_le_bytes());
+    }
+    views.push((offset, bin.len() - offset, ELEMENT_ARRAY_BUFFER));
+
+    let (min, max) = vertices.iter().fold(
+        (
+            Vector3::from_value(f32::INFINITY),
+            Vector3::from_value(f32::NEG_INFINITY),
+        ),
+        |(min, max), v| (min.zip(v.position, f32::min), max.zip(v.position, f32::max)),
+    );
+
+    let views = views
+        .iter()
+        .map(|(offset, length, target)| {
+            format!(
+                r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#,
+                offset, length, target
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    let vec3_accessor = |view: usize| {
+        format!(
+            r#"{{"bufferView":{},"componentType":{},"count":{},"type":"VEC3"}}"#,
+            view,
+            FLOAT,
+            vertices.len()
+        )
+    };
+    let json = format!(
+        concat!(
+            r#"{{"asset":{{"version":"2.0","generator":"quadtree"}},"#,
+            r#""scene":0,"scenes":[{{"nodes":[0]}}],"nodes":[{{"mesh":0}}],"#,
+            r#""meshes":[{{"primitives":[{{"attributes":"#,
+            r#"{{"POSITION":0,"NORMAL":1,"COLOR_0":2}},"indices":3}}]}}],"#,
+            r#""buffers":[{{"byteLength":{}}}],"bufferViews":[{}],"#,
+            r#""accessors":[{},{},{},{}]}}"#
+        ),
+        bin.len(),
+        views,
+        format!(
+            r#"{{"bufferView":0,"componentType":{},"count":{},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+            FLOAT,
+            vertices.len(),
+            min.x,
+            min.y,
+            min.z,
+            max.x,
+            max.y,
+            max.z
+        ),
+        vec3_accessor(1),
+        vec3_accessor(2),
+        format!(
+            r#"{{"bufferView":3,"componentType":{},"count":{},"type":"SCALAR"}}"#,
+            UNSIGNED_INT,
+            triangles.len() * 3
+        ),
+    );
+
+    // Chunks are padded to four bytes, JSON with spaces.
+    let mut json = json.into_bytes();
+    json.resize(json.len().next_multiple_of(4), b' ');
+    bin.resize(bin.len().next_multiple_of(4), 0);
+
+    let length = 12 + 8 + json.len() + 8 + bin.len();
+    w.write_all(b"glTF")?;
+    w.write_all(&2u32.to_le_bytes())?;
+    w.write_all(&(length as u32).to_le_bytes())?;
+    w.write_all(&(json.len() as u32).to_le_bytes())?;
+    w.write_all(b"JSON")?;
+    w.write_all(&json)?;
+    w.write_all(&(bin.len() as u32).to_le_bytes())?;
+    w.write_all(b"BIN\0")?;
+    w.write_all(&bin)
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
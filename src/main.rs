@@ -1,28 +1,181 @@
+mod bench;
+mod biome;
+mod color;
+mod crater;
+mod cubemap;
+mod detail;
+mod erosion;
+mod export;
 mod gen;
 mod input;
 mod orbiter;
+mod planet;
+mod raster;
 mod render;
+mod rtin;
+mod scatter;
+mod shadow;
+mod sun;
 mod tree;
+mod volume;
 
-use cgmath::vec2;
+use std::{
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use cgmath::{vec2, InnerSpace, MetricSpace};
+use export::HeightFormat;
+use gen::Terrain;
 use input::Input;
 use orbiter::Orbiter;
+use planet::{LoadError, Planet, Watcher};
+use sun::Sun;
 use tree::Tree;
 use winit::{
     dpi::{PhysicalPosition, PhysicalSize},
-    event::{Event, KeyboardInput, MouseScrollDelta, WindowEvent},
+    event::{Event, KeyboardInput, MouseScrollDelta, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
 
+/// Window title prefix; the view appends the local time under the camera and
+/// the last `B`/`P` probe result.
+const TITLE: &str = "quadtree";
+
+const USAGE: &str = "usage:
+    quadtree [PLANET]
+    quadtree export-heightmap PLANET OUT_DIR RESOLUTION [png|raw]
+    quadtree export-mesh PLANET OUT.{obj,ply,glb} DEPTH [LATITUDE LONGITUDE]
+    quadtree bench-noise [PLANET] [QUADS]";
+
 fn main() {
+    let mut args = env::args_os().skip(1);
+    match args.next() {
+        Some(command) if command == "export-heightmap" => export_heightmap(args.collect()),
+        Some(command) if command == "export-mesh" => export_mesh(args.collect()),
+        Some(command) if command == "bench-noise" => bench_noise(args.collect()),
+        Some(command) if command == "--help" || command == "-h" => println!("{}", USAGE),
+        path => view(path.map(PathBuf::from)),
+    }
+}
+
+fn export_heightmap(args: Vec<OsString>) {
+    let (planet, out, resolution, format) = match &args[..] {
+        [planet, out, resolution, rest @ ..] if rest.len() <= 1 => {
+            let resolution = resolution.to_str().and_then(|r| r.parse().ok());
+            let format = match rest.first().and_then(|f| f.to_str()) {
+                None | Some("png") => Some(HeightFormat::Png16),
+                Some("raw") => Some(HeightFormat::RawF32),
+                Some(_) => None,
+            };
+            match (resolution, format) {
+                (Some(resolution), Some(format)) => (planet, out, resolution, format),
+                _ => exit_with_usage(),
+            }
+        }
+        _ => exit_with_usage(),
+    };
+
+    let (_, terrain) = load_or_exit(Some(Path::new(planet)));
+    if let Err(error) = export::heightmap(&terrain, Path::new(out), resolution, format) {
+        eprintln!("{}: {}", Path::new(out).display(), error);
+        process::exit(1);
+    }
+}
+
+fn export_mesh(args: Vec<OsString>) {
+    let number = |arg: &OsString| arg.to_str().and_then(|arg| arg.parse::<f32>().ok());
+    let (planet, out, depth, at) = match &args[..] {
+        [planet, out, depth, rest @ ..] => {
+            let depth = depth.to_str().and_then(|depth| depth.parse::<u32>().ok());
+            let at = match rest {
+                [] => Some(None),
+                [latitude, longitude] => number(latitude).zip(number(longitude)).map(Some),
+                _ => None,
+            };
+            match (depth, at) {
+                (Some(depth), Some(at)) => (planet, Path::new(out), depth, at),
+                _ => exit_with_usage(),
+            }
+        }
+        _ => exit_with_usage(),
+    };
+
+    let (_, terrain) = load_or_exit(Some(Path::new(planet)));
+    let point = at.map(|(latitude, longitude)| {
+        let ground = terrain.height_at(latitude, longitude).ground;
+        let direction = gen::direction(latitude, longitude).cast().unwrap();
+        direction * (terrain.radius() as f64 + ground as f64)
+    });
+
+    let quads = Tree::fixed_leaves(&terrain, point, depth);
+    if let Err(error) = export::mesh(&terrain, &quads, out) {
+        eprintln!("{}: {}", out.display(), error);
+        process::exit(1);
+    }
+}
+
+fn bench_noise(args: Vec<OsString>) {
+    let (planet, count) = match &args[..] {
+        [] => (None, Some(200)),
+        [planet] => (Some(planet), Some(200)),
+        [planet, count] => (Some(planet), count.to_str().and_then(|c| c.parse().ok())),
+        _ => exit_with_usage(),
+    };
+    let count = count.unwrap_or_else(|| exit_with_usage());
+
+    let (_, terrain) = load_or_exit(planet.map(Path::new));
+    if !bench::noise(&terrain, count) {
+        process::exit(1);
+    }
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn load_or_exit(path: Option<&Path>) -> (Planet, Terrain) {
+    let loaded = match path {
+        Some(path) => load(path).map_err(|error| format!("{}: {}", path.display(), error)),
+        None => {
+            let planet = Planet::default();
+            Terrain::new(&planet)
+                .map(|terrain| (planet, terrain))
+                .map_err(|error| error.to_string())
+        }
+    };
+
+    loaded.unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    })
+}
+
+fn view(path: Option<PathBuf>) {
+    let (planet, mut terrain) = load_or_exit(path.as_deref());
+    let mut watcher = path.map(Watcher::new);
+    let mut reload = None;
+
     let event_loop = EventLoop::new();
-    let window = WindowBuilder::default().build(&event_loop).unwrap();
+    let window = WindowBuilder::default()
+        .with_title(TITLE)
+        .build(&event_loop)
+        .unwrap();
 
-    let mut renderer = render::init(&window);
+    let mut renderer = render::init(&window, &planet);
     let mut input = Input::default();
-    let mut orbiter = Orbiter::default();
-    let mut tree = Tree::new(&renderer);
+    let mut orbiter = Orbiter::new(&planet, &terrain);
+    let mut sun = Sun::new(&planet);
+    let mut tree = Tree::new(&terrain, &renderer);
+    let mut probe = None;
+    let mut title = TITLE.to_string();
 
     event_loop.run(move |event, _, flow| {
         *flow = ControlFlow::Poll;
@@ -66,13 +219,115 @@ fn main() {
             },
 
             Event::MainEventsCleared => {
-                orbiter.process(&input);
-                tree.process(&orbiter, &renderer);
+                if let Some(watcher) = &mut watcher {
+                    // A newer reload replaces one still running, whose result
+                    // is then dropped.
+                    if watcher.changed() {
+                        reload = Some(load_in_background(watcher.path().to_owned()));
+                    }
+
+                    let loaded = match reload.as_ref().map(Receiver::try_recv) {
+                        Some(Ok(loaded)) => Some(loaded),
+                        Some(Err(TryRecvError::Disconnected)) => {
+                            eprintln!("{}: loading failed", watcher.path().display());
+                            None
+                        }
+                        _ => None,
+                    };
+                    if let Some(loaded) = loaded {
+                        reload = None;
+                        match loaded {
+                            Ok((planet, new_terrain)) => {
+                                terrain = new_terrain;
+                                orbiter.reload(&planet, &terrain);
+                                sun.reload(&planet);
+                                renderer.reload(&planet);
+                                tree.regenerate(&terrain, &renderer);
+                            }
+                            Err(error) => eprintln!("{}: {}", watcher.path().display(), error),
+                        }
+                    }
+                }
+
+                if input.is_key_down_once(VirtualKeyCode::B) {
+                    let direction = orbiter.position().normalize().cast().unwrap();
+                    let (latitude, longitude) = gen::lat_long(direction);
+                    let biome = terrain
+                        .biome_at(latitude, longitude)
+                        .map_or("no biomes", |biome| &biome.name);
+                    let height = terrain.height_at(latitude, longitude);
+                    probe = Some(format!(
+                        "{:.2}, {:.2}: {}, ground {:.2}, depth {:.2}",
+                        latitude,
+                        longitude,
+                        biome,
+                        height.ground,
+                        height.depth()
+                    ));
+                }
+
+                if input.is_key_down_once(VirtualKeyCode::P) {
+                    let position = orbiter.position();
+                    let nearest = tree
+                        .props_within(position, f64::INFINITY)
+                        .into_iter()
+                        .map(|prop| (prop.position.distance(position), prop))
+                        .min_by(|(a, _), (b, _)| a.total_cmp(b));
+                    let found = match nearest {
+                        Some((distance, prop)) => {
+                            let direction = prop.position.normalize().cast().unwrap();
+                            let (latitude, longitude) = gen::lat_long(direction);
+                            format!(
+                                "nearest prop: {} at {:.2}, {:.2}, {:.2} away",
+                                terrain.scatter()[prop.kind].name,
+                                latitude,
+                                longitude,
+                                distance
+                            )
+                        }
+                        None => "no props loaded".to_string(),
+                    };
+                    probe = Some(found);
+                }
+
+                if input.is_key_down_once(VirtualKeyCode::H) {
+                    renderer.toggle_shadows();
+                }
+
+                if input.is_key_down_once(VirtualKeyCode::X) {
+                    let path = export_path(watcher.as_ref().map(Watcher::path));
+                    if let Err(error) = export::mesh(&terrain, &tree.leaves(), &path) {
+                        eprintln!("{}: {}", path.display(), error);
+                    }
+                }
+
+                let direction = orbiter.position().normalize().cast().unwrap();
+                let (_, longitude) = gen::lat_long(direction);
+                let local_time = (sun.hours() + longitude / 15.0).rem_euclid(24.0);
+                let mut next = format!(
+                    "{} - {:02}:{:02}",
+                    TITLE,
+                    local_time as u32,
+                    (local_time.fract() * 60.0) as u32
+                );
+                if let Some(probe) = &probe {
+                    next = format!("{} - {}", next, probe);
+                }
+                if next != title {
+                    window.set_title(&next);
+                    title = next;
+                }
+
+                orbiter.process(&input, &terrain);
+                sun.process(&input);
+                tree.process(&orbiter, &terrain, &renderer);
 
                 let mut meshes = Vec::new();
-                tree.collect_meshes(&mut meshes);
+                let mut oceans = Vec::new();
+                let mut props = Vec::new();
+                tree.collect_meshes(&mut meshes, &mut oceans, &mut props);
 
-                renderer.render(&orbiter, &meshes);
+                renderer.render(&orbiter, &sun, &meshes, &oceans, &props);
                 input.process();
             }
 
@@ -80,3 +335,41 @@ fn main() {
         }
     });
 }
+
+/// Next to the planet file, or in the working directory for the default
+/// planet, named after the planet and the time so that earlier exports stay.
+fn export_path(planet: Option<&Path>) -> PathBuf {
+    let dir = planet
+        .and_then(Path::parent)
+        .unwrap_or_else(|| Path::new(""));
+    let name = planet
+        .and_then(Path::file_stem)
+        .map_or("planet".into(), |stem| stem.to_string_lossy());
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+
+    (0..)
+        .map(|i| match i {
+            0 => dir.join(format!("{}-{}.glb", name, seconds)),
+            i => dir.join(format!("{}-{}-{}.glb", name, seconds, i)),
+        })
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+fn load(path: &Path) -> Result<(Planet, Terrain), LoadError> {
+    let planet = Planet::load(path)?;
+    let terrain = Terrain::new(&planet)?;
+    Ok((planet, terrain))
+}
+
+/// `load` on another thread, so that slow layers like erosion don't stall
+/// the window.
+fn load_in_background(path: PathBuf) -> Receiver<Result<(Planet, Terrain), LoadError>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(load(&path));
+    });
+    receiver
+}
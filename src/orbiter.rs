@@ -4,58 +4,90 @@ use cgmath::{
 };
 use winit::event::VirtualKeyCode;
 
-use crate::{gen::SCALE, input::Input};
+use crate::{
+    gen::{Terrain, FULL_DETAIL},
+    input::Input,
+    planet::Planet,
+};
+
+/// Frames are taken to be this long, in seconds, for gravity.
+pub const FRAME_TIME: f32 = 1.0 / 60.0;
+
+/// Vertically, in degrees.
+const FIELD_OF_VIEW: f32 = 80.0;
 
 pub struct Orbiter {
-    position: Vector3<f32>,
-    velocity: Vector3<f32>,
+    radius: f32,
+    relief: f32,
+    /// At the radius, in m/s².
+    gravity: f32,
+    /// In f64, to stay precise anywhere near an Earth-sized planet.
+    position: Vector3<f64>,
+    velocity: Vector3<f64>,
     rotation: Quaternion<f32>,
     perspective: Perspective,
+    /// Height of the terrain below the camera, as of the last `process`.
+    ground: f32,
 }
 
-impl Default for Orbiter {
-    fn default() -> Self {
+impl Orbiter {
+    pub fn new(planet: &Planet, terrain: &Terrain) -> Self {
         Self {
-            position: vec3(0.0, 0.0, SCALE * 2.0),
+            radius: planet.radius,
+            relief: terrain.relief(),
+            gravity: planet.surface_gravity(),
+            position: vec3(0.0, 0.0, planet.radius as f64 * 2.0),
             velocity: Vector3::zero(),
             rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
             perspective: Perspective::default(),
+            ground: 0.0,
         }
     }
-}
 
-impl Orbiter {
-    pub fn process(&mut self, input: &Input) {
+    pub fn reload(&mut self, planet: &Planet, terrain: &Terrain) {
+        self.radius = planet.radius;
+        self.relief = terrain.relief();
+        self.gravity = planet.surface_gravity();
+    }
+
+    pub fn process(&mut self, input: &Input, terrain: &Terrain) {
+        self.ground = terrain.height(self.position.normalize(), FULL_DETAIL);
+
+        let distance = self.position.magnitude();
         {
-            let gravity_dir = -self.position.normalize();
-            let gravity_strength = 10.0 / self.position.magnitude2();
-            self.velocity += gravity_dir * gravity_strength;
+            let gravity_dir = -self.position / distance;
+            let gravity_strength = self.gravity as f64 * (self.radius as f64 / distance).powi(2);
+            self.velocity += gravity_dir * gravity_strength * (FRAME_TIME * FRAME_TIME) as f64;
         }
 
-        const SPEED: f32 = 0.01;
+        // Faster further out, so that both walking pace on the ground and
+        // crossing the planet from orbit take a sensible time.
+        const THRUST: f64 = 0.001;
+        let altitude = (distance - self.radius as f64).abs().max(1.0);
+        let speed = THRUST * altitude;
 
         if input.is_key_down(VirtualKeyCode::W) {
-            self.velocity += self.forward() * SPEED;
+            self.velocity += self.forward() * speed;
         }
 
         if input.is_key_down(VirtualKeyCode::S) {
-            self.velocity -= self.forward() * SPEED;
+            self.velocity -= self.forward() * speed;
         }
 
         if input.is_key_down(VirtualKeyCode::A) {
-            self.velocity -= self.right() * SPEED;
+            self.velocity -= self.right() * speed;
         }
 
         if input.is_key_down(VirtualKeyCode::D) {
-            self.velocity += self.right() * SPEED;
+            self.velocity += self.right() * speed;
         }
 
         if input.is_key_down(VirtualKeyCode::Q) {
-            self.velocity -= self.up() * SPEED;
+            self.velocity -= self.up() * speed;
         }
 
         if input.is_key_down(VirtualKeyCode::E) {
-            self.velocity += self.up() * SPEED;
+            self.velocity += self.up() * speed;
         }
 
         self.position += self.velocity;
@@ -65,50 +97,114 @@ impl Orbiter {
         self.perspective.recalc(width, height);
     }
 
+    /// For world space moved to put the camera at the origin, which is where
+    /// the renderer draws everything.
     pub fn matrices(&self) -> Matrices {
-        let rotation: Matrix4<f32> = self.rotation.into();
-        let view_to_world = Matrix4::from_translation(self.position) * rotation;
-        let world_to_clip = self.perspective.matrix * view_to_world.inverse_transform().unwrap();
+        let view_to_world: Matrix4<f32> = self.rotation.into();
+        let (near, far) = self.clip_planes();
+        let world_to_clip =
+            self.perspective.matrix(near, far) * view_to_world.inverse_transform().unwrap();
 
         Matrices {
             world_to_clip,
             view_to_world,
+            clip_to_world: world_to_clip.invert().unwrap(),
         }
     }
 
-    pub fn position(&self) -> Vector3<f32> {
+    pub fn position(&self) -> Vector3<f64> {
         self.position
     }
 
-    fn forward(&self) -> Vector3<f32> {
+    /// The far plane reaches the furthest terrain that can stick out over
+    /// the horizon. The near plane is as far out as the highest peaks allow,
+    /// and among them, a fraction of the height above the ground below, so
+    /// that it stays clear of nearby ground on planets of any size.
+    pub fn clip_planes(&self) -> (f32, f32) {
+        /// Of the height above the ground.
+        const GROUND_NEAR: f64 = 0.1;
+        /// Of the far plane, for when the camera is at or below the ground.
+        const MIN_NEAR: f32 = 1e-7;
+
+        let distance = self.position.magnitude();
+        // In f64, since f32 can't resolve a few meters at the radius of the
+        // Earth.
+        let altitude = distance - self.radius as f64 - self.ground as f64;
+        let distance = distance as f32;
+
+        let far = (self.horizon(distance) + self.horizon(self.top())).max(1.0);
+        let floor = ((altitude * GROUND_NEAR) as f32).max(far * MIN_NEAR);
+        let near = ((distance - self.top()) * 0.5).max(floor);
+        (near, far)
+    }
+
+    /// How far terrain can be from ground it shades, which is from the
+    /// highest peaks to the horizon seen from them.
+    pub fn shadow_reach(&self) -> f32 {
+        self.horizon(self.top())
+    }
+
+    /// Corners of the part of the view between two distances along it,
+    /// relative to the camera.
+    pub fn frustum_corners(&self, near: f32, far: f32) -> [Vector3<f32>; 8] {
+        let tan_y = (FIELD_OF_VIEW / 2.0).to_radians().tan();
+        let tan_x = tan_y * self.perspective.aspect;
+        std::array::from_fn(|i| {
+            let depth = if i & 4 == 0 { near } else { far };
+            let x = if i & 1 == 0 { -tan_x } else { tan_x };
+            let y = if i & 2 == 0 { -tan_y } else { tan_y };
+            self.rotation * vec3(x * depth, y * depth, -depth)
+        })
+    }
+
+    /// The direction the camera looks in.
+    pub fn view_direction(&self) -> Vector3<f32> {
         self.rotation * -Vector3::unit_z()
     }
 
-    fn right(&self) -> Vector3<f32> {
-        self.rotation * Vector3::unit_x()
+    fn top(&self) -> f32 {
+        self.radius + self.relief
+    }
+
+    /// Distance to the horizon from `r` away from the center.
+    fn horizon(&self, r: f32) -> f32 {
+        (r * r - self.radius * self.radius).max(0.0).sqrt()
+    }
+
+    fn forward(&self) -> Vector3<f64> {
+        self.view_direction().cast().unwrap()
     }
 
-    fn up(&self) -> Vector3<f32> {
-        self.rotation * Vector3::unit_y()
+    fn right(&self) -> Vector3<f64> {
+        self.axis(Vector3::unit_x())
+    }
+
+    fn up(&self) -> Vector3<f64> {
+        self.axis(Vector3::unit_y())
+    }
+
+    fn axis(&self, view: Vector3<f32>) -> Vector3<f64> {
+        (self.rotation * view).cast().unwrap()
     }
 }
 
 struct Perspective {
-    matrix: Matrix4<f32>,
+    aspect: f32,
 }
 
 impl Default for Perspective {
     fn default() -> Self {
-        Self {
-            matrix: Matrix4::identity(),
-        }
+        Self { aspect: 1.0 }
     }
 }
 
 impl Perspective {
     fn recalc(&mut self, width: u32, height: u32) {
-        let (width, height) = (width as f32, height as f32);
-        self.matrix = perspective(Deg(80.0), width / height, 0.01, 512.0);
+        self.aspect = width as f32 / height as f32;
+    }
+
+    fn matrix(&self, near: f32, far: f32) -> Matrix4<f32> {
+        perspective(Deg(FIELD_OF_VIEW), self.aspect, near, far)
     }
 }
 
@@ -117,7 +213,49 @@ impl Perspective {
 pub struct Matrices {
     world_to_clip: Matrix4<f32>,
     view_to_world: Matrix4<f32>,
+    /// For turning screen positions into directions from the camera.
+    clip_to_world: Matrix4<f32>,
 }
 
 unsafe impl Zeroable for Matrices {}
 unsafe impl Pod for Matrices {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Above ground that is `ground` high, at `altitude` over it.
+    fn orbiter(radius: f32, relief: f32, ground: f32, altitude: f64) -> Orbiter {
+        Orbiter {
+            radius,
+            relief,
+            gravity: 9.81,
+            position: Vector3::unit_z() * (radius as f64 + ground as f64 + altitude),
+            velocity: Vector3::zero(),
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            perspective: Perspective::default(),
+            ground,
+        }
+    }
+
+    #[test]
+    fn clip_planes_at_ground_level() {
+        for (radius, relief) in [(100.0, 10.0), (6.371e6, 1e4)] {
+            let orbiter = orbiter(radius, relief, relief * 0.5, 2.0);
+            let (near, far) = orbiter.clip_planes();
+            assert!(near > 0.0 && near <= 0.5, "near {} at {}", near, radius);
+            assert!(far >= orbiter.horizon(orbiter.top()), "far {}", far);
+        }
+    }
+
+    #[test]
+    fn clip_planes_from_orbit() {
+        for (radius, relief) in [(100.0, 10.0), (6.371e6, 1e4)] {
+            let orbiter = orbiter(radius, relief, 0.0, radius as f64);
+            let (near, far) = orbiter.clip_planes();
+            let clearance = radius * 2.0 - orbiter.top();
+            assert!((near - clearance * 0.5).abs() <= clearance * 1e-3);
+            assert!(far >= orbiter.horizon(radius * 2.0) && far > near * 2.0);
+        }
+    }
+}
@@ -0,0 +1,657 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use ron::error::{Span, SpannedError};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Planet {
+    /// In meters, like every other length in the file.
+    pub radius: f32,
+    /// Height of an elevation of 1 from the layers.
+    #[serde(default = "default_elevation_scale")]
+    pub elevation_scale: f32,
+    /// Largest height above or depth below the radius. When given, it
+    /// replaces `elevation_scale` with whatever makes the layers reach it.
+    #[serde(default)]
+    pub relief: Option<f32>,
+    /// At the radius, in m/s². Without it, gravity follows from the radius
+    /// and the mean density of Earth.
+    #[serde(default)]
+    pub gravity: Option<f32>,
+    #[serde(default)]
+    pub seed: u32,
+    pub layers: Vec<Layer>,
+    #[serde(default)]
+    pub coloring: Coloring,
+    #[serde(default)]
+    pub biomes: Option<Biomes>,
+    #[serde(default)]
+    pub ocean: Option<Ocean>,
+    /// Vertex attributes to upload on top of position, normal and color.
+    #[serde(default)]
+    pub attributes: Vec<Attribute>,
+    #[serde(default)]
+    pub detail: Option<Detail>,
+    /// How far, in world units, quad meshes may stray from their full grid
+    /// when dropping triangles on flat terrain. Quads whose whole grid stays
+    /// within it, and that already sample every octave of the noise, are not
+    /// subdivided any further. Without it, every quad is a full grid.
+    #[serde(default)]
+    pub max_mesh_error: Option<f32>,
+    #[serde(default)]
+    pub scatter: Vec<Scatter>,
+    #[serde(default)]
+    pub caves: Option<Caves>,
+    #[serde(default)]
+    pub atmosphere: Option<Atmosphere>,
+    #[serde(default)]
+    pub sun: Sun,
+    #[serde(default)]
+    pub shadows: Shadows,
+}
+
+impl Default for Planet {
+    fn default() -> Self {
+        Self {
+            radius: 100.0,
+            elevation_scale: 100.0,
+            relief: None,
+            gravity: Some(3.6),
+            seed: 0,
+            layers: vec![Layer::Noise {
+                octaves: 4,
+                frequency: 2.0,
+                lacunarity: 2.5,
+                amplitude: 0.1,
+                persistence: 0.3,
+                adaptive: false,
+            }],
+            coloring: Coloring::default(),
+            biomes: None,
+            ocean: None,
+            attributes: Vec::new(),
+            detail: None,
+            max_mesh_error: None,
+            scatter: Vec::new(),
+            caves: None,
+            atmosphere: None,
+            sun: Sun::default(),
+            shadows: Shadows::default(),
+        }
+    }
+}
+
+impl Planet {
+    /// Loads a planet file, with paths in it relative to the file.
+    pub fn load(path: &Path) -> Result<Self, LoadError> {
+        let source = fs::read_to_string(path).map_err(LoadError::Io)?;
+        let mut planet = Self::parse(&source)?;
+        if let Some(dir) = path.parent() {
+            planet.resolve_paths(dir);
+        }
+        Ok(planet)
+    }
+
+    pub fn surface_gravity(&self) -> f32 {
+        const GRAVITATIONAL_CONSTANT: f32 = 6.674e-11;
+        const EARTH_DENSITY: f32 = 5514.0;
+
+        self.gravity.unwrap_or_else(|| {
+            4.0 / 3.0 * std::f32::consts::PI * GRAVITATIONAL_CONSTANT * EARTH_DENSITY * self.radius
+        })
+    }
+
+    pub fn parse(source: &str) -> Result<Self, LoadError> {
+        let mut de = ron::Deserializer::from_str(source)
+            .map_err(|error| LoadError::parse(String::new(), error))?;
+
+        let planet = serde_path_to_error::deserialize(&mut de).map_err(|error| {
+            let field = error.path().to_string();
+            LoadError::parse(field, de.span_error(error.into_inner()))
+        })?;
+
+        de.end()
+            .map_err(|error| LoadError::parse(String::new(), de.span_error(error)))?;
+
+        Self::validate(&planet)?;
+        Ok(planet)
+    }
+
+    /// Catches values that parse fine but would break generation.
+    fn validate(&self) -> Result<(), LoadError> {
+        // Gravity, LOD distances and clip planes all scale with these.
+        if self.radius <= 0.0 || !self.radius.is_finite() {
+            return Err(LoadError::invalid("radius".into(), "must be above 0"));
+        }
+        if self
+            .relief
+            .is_some_and(|relief| relief <= 0.0 || !relief.is_finite())
+        {
+            return Err(LoadError::invalid("relief".into(), "must be above 0"));
+        }
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            let field = format!("layers[{}]", i);
+            if let Layer::Erosion { resolution: 0, .. } = layer {
+                return Err(LoadError::invalid(field, "resolution must be at least 1"));
+            }
+
+            // Adaptive octaves count how many times it fits into the
+            // frequency of the grid.
+            if let Layer::Noise { lacunarity, .. } = *layer {
+                if lacunarity <= 1.0 {
+                    return Err(LoadError::invalid(field, "lacunarity must be above 1"));
+                }
+            }
+
+            if let Layer::Craters {
+                min_radius,
+                max_radius,
+                exponent,
+                ..
+            } = *layer
+            {
+                if min_radius <= 0.0 {
+                    return Err(LoadError::invalid(field, "min_radius must be above 0"));
+                }
+                if max_radius <= min_radius {
+                    return Err(LoadError::invalid(
+                        field,
+                        "max_radius must be above min_radius",
+                    ));
+                }
+                if exponent == 0.0 {
+                    return Err(LoadError::invalid(field, "exponent must not be 0"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn resolve_paths(&mut self, dir: &Path) {
+        for layer in &mut self.layers {
+            if let Layer::Heightmap { source, .. } = layer {
+                source.resolve_paths(dir);
+            }
+        }
+
+        if let Coloring::Texture(source) = &mut self.coloring {
+            source.resolve_paths(dir);
+        }
+    }
+}
+
+fn default_elevation_scale() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum Layer {
+    /// With `adaptive`, `octaves` is an upper limit and deeper quads
+    /// evaluate more octaves as their grid gets fine enough to show them.
+    Noise {
+        octaves: u32,
+        frequency: f32,
+        lacunarity: f32,
+        amplitude: f32,
+        persistence: f32,
+        #[serde(default)]
+        adaptive: bool,
+    },
+    /// Craters with radii in radians, following a power-law size distribution.
+    /// Depth and rim height are relative to the radius of each crater.
+    Craters {
+        count: u32,
+        min_radius: f32,
+        max_radius: f32,
+        exponent: f32,
+        depth: f32,
+        rim: f32,
+    },
+    /// Bakes the layers listed before it into a cube map with `resolution`
+    /// cells along each face edge, and runs droplet-based hydraulic erosion
+    /// over it. Layers listed after it add detail on top of the eroded base.
+    Erosion {
+        resolution: u32,
+        droplets: u32,
+        lifetime: u32,
+        inertia: f32,
+        capacity: f32,
+        min_slope: f32,
+        erode: f32,
+        deposit: f32,
+        evaporate: f32,
+        gravity: f32,
+    },
+    /// Elevation data from images, mapped linearly from `min` for black to
+    /// `max` for white.
+    Heightmap {
+        source: RasterSource,
+        min: f32,
+        max: f32,
+    },
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum RasterSource {
+    Equirectangular(ImageFile),
+    /// Faces in the order North, South, East, West, Up, Down.
+    Cube(Box<[ImageFile; 6]>),
+}
+
+impl RasterSource {
+    fn resolve_paths(&mut self, dir: &Path) {
+        match self {
+            Self::Equirectangular(file) => file.resolve_path(dir),
+            Self::Cube(files) => files.iter_mut().for_each(|file| file.resolve_path(dir)),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum ImageFile {
+    Png(PathBuf),
+    /// Little-endian samples without a header.
+    Raw {
+        path: PathBuf,
+        width: u32,
+        height: u32,
+        format: RawFormat,
+    },
+}
+
+impl ImageFile {
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Png(path) => path,
+            Self::Raw { path, .. } => path,
+        }
+    }
+
+    fn resolve_path(&mut self, dir: &Path) {
+        let path = match self {
+            Self::Png(path) => path,
+            Self::Raw { path, .. } => path,
+        };
+        *path = dir.join(&*path);
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub enum RawFormat {
+    U16,
+    F32,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum Coloring {
+    Random,
+    Flat([f32; 3]),
+    Rules(Vec<ColorRule>),
+    /// Biome colors with the given rules applied on top.
+    Biomes(Vec<ColorRule>),
+    /// Colors from an sRGB image.
+    Texture(RasterSource),
+}
+
+impl Default for Coloring {
+    fn default() -> Self {
+        Self::Rules(vec![
+            ColorRule::Ramp(vec![
+                (-10.0, [0.55, 0.5, 0.35]),
+                (-2.0, [0.25, 0.45, 0.15]),
+                (6.0, [0.15, 0.3, 0.1]),
+                (12.0, [0.45, 0.4, 0.35]),
+            ]),
+            ColorRule::Slope {
+                from: 0.15,
+                to: 0.3,
+                color: [0.35, 0.33, 0.3],
+            },
+            ColorRule::Elevation {
+                from: 13.0,
+                to: 15.0,
+                color: [0.95, 0.95, 0.95],
+            },
+            ColorRule::Latitude {
+                from: 70.0,
+                to: 80.0,
+                color: [0.95, 0.95, 0.95],
+            },
+        ])
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum ColorRule {
+    Ramp(Vec<(f32, [f32; 3])>),
+    Elevation { from: f32, to: f32, color: [f32; 3] },
+    Slope { from: f32, to: f32, color: [f32; 3] },
+    Latitude { from: f32, to: f32, color: [f32; 3] },
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Biomes {
+    /// Temperature at sea level on the equator.
+    pub equator_temperature: f32,
+    /// Temperature at sea level on the poles.
+    pub pole_temperature: f32,
+    /// Temperature drop per unit of height.
+    pub lapse_rate: f32,
+    pub moisture: Noise,
+    pub table: Vec<Biome>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Biome {
+    pub name: String,
+    pub color: [f32; 3],
+    pub temperature: f32,
+    /// Between 0 and 1.
+    pub moisture: f32,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Ocean {
+    /// Sea level as a height above the planet radius.
+    pub level: f32,
+    pub color: [f32; 3],
+}
+
+/// Procedural texture detail added to the vertex colors up close, blending
+/// from ground to rock on steep slopes and high up.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Detail {
+    /// Texture repeats per unit of distance, at the coarsest of two scales.
+    pub scale: f32,
+    /// How far the detail brightens and darkens the color, between 0 and 1.
+    pub strength: f32,
+    pub rock_slope: (f32, f32),
+    pub rock_elevation: (f32, f32),
+}
+
+/// Props placed on the surface, in the same places for the same seed.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Scatter {
+    pub name: String,
+    pub shape: Shape,
+    pub color: [f32; 3],
+    /// Depth of the quads that place the props. They show up once the tree
+    /// is subdivided that far, and go away when it collapses above it.
+    pub depth: u32,
+    /// Props per unit of surface area.
+    pub density: f32,
+    /// Range of sizes, with 1 being about one unit tall.
+    pub size: (f32, f32),
+    pub slope: (f32, f32),
+    pub elevation: (f32, f32),
+    /// Names of the biomes to place in, or empty for anywhere.
+    #[serde(default)]
+    pub biomes: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub enum Shape {
+    Rock,
+    Tree,
+}
+
+/// Meshes quads from a 3D density field instead of a heightfield, so that
+/// noise can carve caves, arches and overhangs out of the ground. Each quad
+/// becomes a stack of voxel chunks, from the deepest caves below it to
+/// above its highest point.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Caves {
+    /// Sampled in 3D, over positions scaled down to the unit sphere.
+    pub noise: Noise,
+    /// Where the noise is above it, the ground is hollow.
+    pub threshold: f32,
+    /// How far below the surface caves reach, in world units.
+    pub depth: f32,
+}
+
+/// Air around the planet that scatters light, for the sky seen from the
+/// ground, the halo around the planet seen from space, and the haze over
+/// distant terrain. Coefficients are per meter at the planet radius, and
+/// fall off exponentially with height.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Atmosphere {
+    /// Where the air ends, from the planet center.
+    pub radius: f32,
+    /// Scattering by molecules, for red, green and blue.
+    pub rayleigh: [f32; 3],
+    /// Height over which `rayleigh` falls off by a factor of e.
+    pub rayleigh_height: f32,
+    /// Scattering by haze and dust, the same for every color.
+    pub mie: f32,
+    pub mie_height: f32,
+    /// Between -1 and 1, how much Mie scattering favors light going on
+    /// forward over light bouncing back.
+    pub mie_anisotropy: f32,
+    /// Brightness of the light being scattered.
+    pub intensity: f32,
+}
+
+/// The only light, going around the planet once a day.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Sun {
+    /// Hours past midnight at longitude 0 when the planet loads.
+    pub time: f32,
+    /// In seconds, or 0 for a sun that stays put.
+    pub day_length: f32,
+    /// Latitude in degrees where the sun is straight overhead at noon, for
+    /// the season.
+    pub declination: f32,
+    /// Light on the side facing away from the sun, as a fraction of full
+    /// sunlight.
+    pub ambient: f32,
+}
+
+impl Default for Sun {
+    fn default() -> Self {
+        Self {
+            time: 10.0,
+            day_length: 0.0,
+            declination: 0.0,
+            ambient: 0.03,
+        }
+    }
+}
+
+/// Terrain casting shadows from the sun, in cascades of shadow maps that
+/// each cover a further slice of the view. `H` turns them on and off.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Shadows {
+    pub enabled: bool,
+    pub quality: ShadowQuality,
+}
+
+impl Default for Shadows {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            quality: ShadowQuality::Medium,
+        }
+    }
+}
+
+/// Higher takes larger shadow maps for sharper shadows, and filters them
+/// over more texels for softer edges.
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+pub enum ShadowQuality {
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+pub enum Attribute {
+    Uv,
+    Tangent,
+    Elevation,
+    Slope,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Noise {
+    pub octaves: u32,
+    pub frequency: f32,
+    pub lacunarity: f32,
+    pub amplitude: f32,
+    pub persistence: f32,
+}
+
+pub enum LoadError {
+    Io(io::Error),
+    Image(PathBuf, String),
+    Parse {
+        field: String,
+        span: Span,
+        message: String,
+    },
+    /// A value out of range, in the field at the given path.
+    Invalid {
+        field: String,
+        message: String,
+    },
+}
+
+impl LoadError {
+    fn invalid(field: String, message: &str) -> Self {
+        Self::Invalid {
+            field,
+            message: message.to_string(),
+        }
+    }
+
+    fn parse(field: String, error: SpannedError) -> Self {
+        Self::Parse {
+            field,
+            span: error.span,
+            message: error.code.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{}", error),
+            Self::Image(path, message) => write!(f, "{}: {}", path.display(), message),
+            Self::Parse {
+                field,
+                span,
+                message,
+            } if field.is_empty() || field == "." => write!(f, "{}: {}", span, message),
+            Self::Parse {
+                field,
+                span,
+                message,
+            } => write!(f, "{}: in `{}`: {}", span, field, message),
+            Self::Invalid { field, message } => write!(f, "in `{}`: {}", field, message),
+        }
+    }
+}
+
+pub struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl Watcher {
+    const INTERVAL: Duration = Duration::from_millis(250);
+
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            modified: modified(&path),
+            path,
+            last_check: Instant::now(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < Self::INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+
+        let modified = modified(&self.path);
+        if modified.is_some() && modified != self.modified {
+            self.modified = modified;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_errors_name_the_nested_field() {
+        let source = r#"Planet(
+            radius: 100.0,
+            elevation_scale: 100.0,
+            seed: 0,
+            layers: [
+                Noise(
+                    octaves: 4,
+                    frequency: 2.0,
+                    lacunarity: 2.5,
+                    amplitude: 0.1,
+                    persistence: 0.3,
+                ),
+                Noise(
+                    octaves: "many",
+                    frequency: 2.0,
+                    lacunarity: 2.5,
+                    amplitude: 0.1,
+                    persistence: 0.3,
+                ),
+            ],
+            coloring: Random,
+        )"#;
+        let message = match Planet::parse(source) {
+            Ok(_) => panic!("parsed a string as octaves"),
+            Err(error) => error.to_string(),
+        };
+        assert!(message.starts_with("14:"), "{}", message);
+        assert!(
+            message.contains("in `layers[1].Noise.octaves`"),
+            "{}",
+            message
+        );
+    }
+}
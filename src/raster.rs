@@ -0,0 +1,218 @@
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::Path,
+};
+
+use cgmath::{vec2, vec3, Vector2, Vector3};
+use png::{BitDepth, Transformations};
+
+use crate::{
+    gen,
+    planet::{ImageFile, LoadError, RasterSource, RawFormat},
+    tree::Facing,
+};
+
+/// An image wrapped around the sphere, sampled bilinearly by direction.
+pub enum Raster {
+    Equirectangular(Image),
+    /// Faces in the order of `Facing::all`, oriented like `Facing::orient`.
+    Cube(Box<[Image; 6]>),
+}
+
+impl Raster {
+    pub fn load(source: &RasterSource) -> Result<Self, LoadError> {
+        Ok(match source {
+            RasterSource::Equirectangular(file) => Self::Equirectangular(Image::load(file)?),
+            RasterSource::Cube(files) => {
+                let [n, s, e, w, u, d] = &**files;
+                let images = [
+                    Image::load(n)?,
+                    Image::load(s)?,
+                    Image::load(e)?,
+                    Image::load(w)?,
+                    Image::load(u)?,
+                    Image::load(d)?,
+                ];
+
+                // Faces meet at their edges, which only line up if they
+                // are all the same square.
+                let size = images[0].width;
+                for (image, file) in images.iter().zip(&**files) {
+                    if image.width != size || image.height != size {
+                        return Err(LoadError::Image(
+                            file.path().to_path_buf(),
+                            format!(
+                                "expected {0}x{0} like the first face, found {1}x{2}",
+                                size, image.width, image.height
+                            ),
+                        ));
+                    }
+                }
+                Self::Cube(Box::new(images))
+            }
+        })
+    }
+
+    /// Linear color from an image in sRGB.
+    pub fn sample_color(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        vec3(
+            self.sample(direction, 0),
+            self.sample(direction, 1),
+            self.sample(direction, 2),
+        )
+        .map(srgb_to_linear)
+    }
+
+    /// Sample of the given channel, between 0 and 1.
+    pub fn sample(&self, direction: Vector3<f32>, channel: usize) -> f32 {
+        match self {
+            Self::Equirectangular(image) => {
+                let (latitude, longitude) = gen::lat_long(direction);
+                let uv = vec2((longitude + 180.0) / 360.0, (90.0 - latitude) / 180.0);
+                image.sample(uv, channel, true)
+            }
+            Self::Cube(images) => {
+                let facing = Facing::of(direction);
+                let uv = facing.project(direction).unwrap();
+                images[facing as usize].sample((uv + vec2(1.0, 1.0)) * 0.5, channel, false)
+            }
+        }
+    }
+}
+
+pub struct Image {
+    width: usize,
+    height: usize,
+    channels: usize,
+    /// Normalized to between 0 and 1, row by row from the top.
+    data: Vec<f32>,
+}
+
+impl Image {
+    pub fn load(file: &ImageFile) -> Result<Self, LoadError> {
+        match file {
+            ImageFile::Png(path) => Self::load_png(path),
+            ImageFile::Raw {
+                path,
+                width,
+                height,
+                format,
+            } => Self::load_raw(path, *width as usize, *height as usize, *format),
+        }
+        .and_then(Self::check)
+        .map_err(|message| LoadError::Image(file.path().to_path_buf(), message))
+    }
+
+    /// Rejects images without a pixel to sample, or with too few samples
+    /// for their size.
+    fn check(self) -> Result<Self, String> {
+        if self.width == 0 || self.height == 0 || self.channels == 0 {
+            return Err(format!(
+                "image is empty, {}x{} with {} channels",
+                self.width, self.height, self.channels
+            ));
+        }
+        let expected = self.width * self.height * self.channels;
+        if self.data.len() != expected {
+            return Err(format!(
+                "expected {} samples for {}x{} pixels, found {}",
+                expected,
+                self.width,
+                self.height,
+                self.data.len()
+            ));
+        }
+        Ok(self)
+    }
+
+    fn load_png(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|error| error.to_string())?;
+        let mut decoder = png::Decoder::new(BufReader::new(file));
+        decoder.set_transformations(Transformations::EXPAND);
+
+        let mut reader = decoder.read_info().map_err(|error| error.to_string())?;
+        let size = reader
+            .output_buffer_size()
+            .ok_or_else(|| "image too large".to_string())?;
+        let mut buffer = vec![0; size];
+        let info = reader
+            .next_frame(&mut buffer)
+            .map_err(|error| error.to_string())?;
+        buffer.truncate(info.buffer_size());
+
+        let data = match info.bit_depth {
+            BitDepth::Sixteen => buffer
+                .chunks_exact(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]) as f32 / u16::MAX as f32)
+                .collect(),
+            _ => buffer.iter().map(|&b| b as f32 / u8::MAX as f32).collect(),
+        };
+
+        Ok(Self {
+            width: info.width as usize,
+            height: info.height as usize,
+            channels: info.color_type.samples(),
+            data,
+        })
+    }
+
+    fn load_raw(
+        path: &Path,
+        width: usize,
+        height: usize,
+        format: RawFormat,
+    ) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|error| error.to_string())?;
+        let data: Vec<f32> = match format {
+            RawFormat::U16 => bytes
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]) as f32 / u16::MAX as f32)
+                .collect(),
+            RawFormat::F32 => bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+        };
+
+        Ok(Self {
+            width,
+            height,
+            channels: 1,
+            data,
+        })
+    }
+
+    /// `uv` is between 0 and 1 over the whole image.
+    fn sample(&self, uv: Vector2<f32>, channel: usize, wrap_x: bool) -> f32 {
+        let channel = channel.min(self.channels - 1);
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = (uv.y * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let column = |x: f32| {
+            let x = x as isize;
+            if wrap_x {
+                x.rem_euclid(self.width as isize) as usize
+            } else {
+                x.clamp(0, self.width as isize - 1) as usize
+            }
+        };
+        let (x0, x1) = (column(x0), column(x0 + 1.0));
+        let (y0, y1) = (y0 as usize, (y0 as usize + 1).min(self.height - 1));
+
+        let get = |x: usize, y: usize| self.data[(y * self.width + x) * self.channels + channel];
+        let top = get(x0, y0) + (get(x1, y0) - get(x0, y0)) * fx;
+        let bottom = get(x0, y1) + (get(x1, y1) - get(x0, y1)) * fx;
+        top + (bottom - top) * fy
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
@@ -1,139 +1,638 @@
-use std::{iter::once, mem::size_of, rc::Rc};
+use std::{
+    borrow::Cow,
+    iter::once,
+    mem::size_of,
+    num::{NonZeroU32, NonZeroU64},
+    ops::Range,
+    rc::Rc,
+};
 
 use bytemuck::{cast_slice, Pod, Zeroable};
-use cgmath::Vector3;
+use cgmath::{Matrix4, Vector2, Vector3, Vector4, Zero};
 use futures_lite::future;
 use wgpu::{
-    include_wgsl,
     util::{BufferInitDescriptor, DeviceExt},
-    vertex_attr_array, Backends, BindGroup, BindGroupDescriptor, BindGroupEntry,
-    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer,
-    BufferBinding, BufferBindingType, BufferUsages, Color, DepthBiasState, DepthStencilState,
-    Device, DeviceDescriptor, Extent3d, Face, Features, FragmentState, FrontFace, IndexFormat,
-    Instance, Limits, LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor, PolygonMode,
-    PresentMode, PrimitiveState, PrimitiveTopology, Queue, RenderPassColorAttachment,
+    vertex_attr_array, AddressMode, Backends, BindGroup, BindGroupDescriptor, BindGroupEntry,
+    BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
+    BlendState, Buffer, BufferBinding, BufferBindingType, BufferDescriptor, BufferUsages, Color,
+    ColorTargetState, ColorWrites, CompareFunction, DepthBiasState, DepthStencilState, Device,
+    DeviceDescriptor, Extent3d, Face, Features, FilterMode, FragmentState, FrontFace,
+    ImageCopyTexture, ImageDataLayout, IndexFormat, Instance, Limits, LoadOp, MultisampleState,
+    Operations, Origin3d, PipelineLayout, PipelineLayoutDescriptor, PolygonMode, PresentMode,
+    PrimitiveState, PrimitiveTopology, Queue, RenderPass, RenderPassColorAttachment,
     RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline,
-    RenderPipelineDescriptor, RequestAdapterOptions, ShaderStages, StencilState, Surface,
-    SurfaceConfiguration, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
-    TextureView, VertexBufferLayout, VertexState, VertexStepMode,
+    RenderPipelineDescriptor, RequestAdapterOptions, SamplerBindingType, SamplerDescriptor,
+    ShaderModule, ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilState, Surface,
+    SurfaceConfiguration, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension,
+    VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode,
 };
 use winit::window::Window;
 
-use crate::{orbiter::Matrices, orbiter::Orbiter};
+use crate::{
+    detail,
+    orbiter::Matrices,
+    orbiter::Orbiter,
+    planet::{Attribute, Planet, ShadowQuality},
+    scatter,
+    shadow::{Cascades, CASCADES},
+    sun::Sun,
+};
 
 pub struct State {
     surface: Surface,
     format: TextureFormat,
     device: Device,
     queue: Queue,
+    layout: VertexLayout,
+    pipeline_layout: PipelineLayout,
+    shadow_pipeline_layout: PipelineLayout,
     pipeline: RenderPipeline,
+    ocean_pipeline: RenderPipeline,
+    prop_pipeline: RenderPipeline,
+    sky_pipeline: RenderPipeline,
+    /// Renders terrain into the shadow maps.
+    shadow_pipeline: RenderPipeline,
+    /// Meshes for the `Scatter` entries of the planet.
+    props: Vec<Mesh>,
     camera_buffer: Buffer,
+    /// Filled in with the camera position every frame.
+    atmosphere: AtmosphereUniform,
+    atmosphere_buffer: Buffer,
+    sun_buffer: Buffer,
     camera_group: BindGroup,
+    detail_buffer: Buffer,
+    detail_group: BindGroup,
+    /// Distance after which the detail textures repeat exactly.
+    detail_period: f64,
+    node_layout: BindGroupLayout,
+    /// A `NodeUniform` for every mesh drawn in a frame, `NODE_STRIDE` apart.
+    node_buffer: Buffer,
+    node_group: BindGroup,
+    node_capacity: usize,
+    shadows_enabled: bool,
+    shadow_buffer: Buffer,
+    shadow_layout: BindGroupLayout,
+    shadow_maps: ShadowMaps,
+    /// The matrix of each cascade, `NODE_STRIDE` apart.
+    caster_buffer: Buffer,
+    caster_group: BindGroup,
     depth_buffer: TextureView,
 }
 
-pub fn init(window: &Window) -> State {
+/// Bytes between the uniforms of consecutive nodes, which is the offset
+/// alignment wgpu guarantees.
+const NODE_STRIDE: usize = 256;
+
+pub fn init(window: &Window, planet: &Planet) -> State {
     let instance = Instance::new(Backends::all());
     let surface = unsafe { instance.create_surface(window) };
     let init_device = future::block_on(init_device(&instance, &surface));
     let (format, device, queue) = (init_device.format, init_device.device, init_device.queue);
 
-    let shader = device.create_shader_module(&include_wgsl!("shader.wgsl"));
-
     let camera_buffer = device.create_buffer_init(&BufferInitDescriptor {
         label: None,
         contents: &[0; size_of::<Matrices>()],
         usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
     });
 
+    let atmosphere = AtmosphereUniform::new(planet);
+    let atmosphere_buffer = device.create_buffer_init(&BufferInitDescriptor {
+        label: None,
+        contents: cast_slice(&[atmosphere]),
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+    });
+
+    let sun_buffer = device.create_buffer(&BufferDescriptor {
+        label: None,
+        size: size_of::<SunUniform>() as u64,
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
     let camera_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    });
+
+    let camera_group = device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &camera_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &camera_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &atmosphere_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &sun_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+        ],
+    });
+
+    let detail_buffer = device.create_buffer_init(&BufferInitDescriptor {
+        label: None,
+        contents: cast_slice(&[DetailUniform::new(planet)]),
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+    });
+
+    let detail_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    });
+
+    let detail_texture = create_detail_texture(&device, &queue);
+    let detail_sampler = device.create_sampler(&SamplerDescriptor {
+        address_mode_u: AddressMode::Repeat,
+        address_mode_v: AddressMode::Repeat,
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        mipmap_filter: FilterMode::Linear,
+        ..Default::default()
+    });
+
+    let detail_group = device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &detail_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&detail_texture),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&detail_sampler),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &detail_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+        ],
+    });
+
+    let node_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
         label: None,
         entries: &[BindGroupLayoutEntry {
             binding: 0,
             visibility: ShaderStages::VERTEX,
             ty: BindingType::Buffer {
                 ty: BufferBindingType::Uniform,
-                has_dynamic_offset: false,
-                min_binding_size: None,
+                has_dynamic_offset: true,
+                min_binding_size: NonZeroU64::new(size_of::<NodeUniform>() as u64),
             },
             count: None,
         }],
     });
 
-    let camera_group = device.create_bind_group(&BindGroupDescriptor {
+    let node_capacity = 64;
+    let (node_buffer, node_group) = create_node_group(&device, &node_layout, node_capacity);
+
+    let shadow_buffer = device.create_buffer(&BufferDescriptor {
         label: None,
-        layout: &camera_group_layout,
+        size: size_of::<ShadowUniform>() as u64,
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let shadow_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Comparison),
+                count: None,
+            },
+        ],
+    });
+
+    let shadow_maps = ShadowMaps::new(
+        &device,
+        &shadow_layout,
+        &shadow_buffer,
+        planet.shadows.quality,
+    );
+
+    let caster_buffer = device.create_buffer(&BufferDescriptor {
+        label: None,
+        size: (CASCADES * NODE_STRIDE) as u64,
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let caster_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[BindGroupLayoutEntry {
+            binding: 3,
+            visibility: ShaderStages::VERTEX,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: NonZeroU64::new(size_of::<Matrix4<f32>>() as u64),
+            },
+            count: None,
+        }],
+    });
+
+    let caster_group = device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &caster_layout,
+        entries: &[BindGroupEntry {
+            binding: 3,
+            resource: BindingResource::Buffer(BufferBinding {
+                buffer: &caster_buffer,
+                offset: 0,
+                size: NonZeroU64::new(size_of::<Matrix4<f32>>() as u64),
+            }),
+        }],
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: None,
+        bind_group_layouts: &[
+            &camera_group_layout,
+            &detail_group_layout,
+            &node_layout,
+            &shadow_layout,
+        ],
+        push_constant_ranges: &[],
+    });
+
+    // Group 3 holds the shadow maps everywhere else, which can't be bound
+    // while they are being rendered to.
+    let shadow_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: None,
+        bind_group_layouts: &[
+            &camera_group_layout,
+            &detail_group_layout,
+            &node_layout,
+            &caster_layout,
+        ],
+        push_constant_ranges: &[],
+    });
+
+    let layout = VertexLayout::new(&planet.attributes);
+    let [pipeline, ocean_pipeline, prop_pipeline, sky_pipeline, shadow_pipeline] = create_pipelines(
+        &device,
+        [&pipeline_layout, &shadow_pipeline_layout],
+        format,
+        &layout,
+    );
+
+    let depth_buffer = create_depth_buffer(&device, 1, 1);
+
+    let mut state = State {
+        surface,
+        format,
+        device,
+        queue,
+        layout,
+        pipeline_layout,
+        shadow_pipeline_layout,
+        pipeline,
+        ocean_pipeline,
+        prop_pipeline,
+        sky_pipeline,
+        shadow_pipeline,
+        props: Vec::new(),
+        camera_buffer,
+        atmosphere,
+        atmosphere_buffer,
+        sun_buffer,
+        camera_group,
+        detail_buffer,
+        detail_group,
+        detail_period: detail_period(planet),
+        node_layout,
+        node_buffer,
+        node_group,
+        node_capacity,
+        shadows_enabled: planet.shadows.enabled,
+        shadow_buffer,
+        shadow_layout,
+        shadow_maps,
+        caster_buffer,
+        caster_group,
+        depth_buffer,
+    };
+    state.props = state.create_props(planet);
+    state
+}
+
+/// Room for the uniforms of `capacity` nodes.
+fn create_node_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    capacity: usize,
+) -> (Buffer, BindGroup) {
+    let buffer = device.create_buffer(&BufferDescriptor {
+        label: None,
+        size: (capacity * NODE_STRIDE) as u64,
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let group = device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout,
         entries: &[BindGroupEntry {
             binding: 0,
             resource: BindingResource::Buffer(BufferBinding {
-                buffer: &camera_buffer,
+                buffer: &buffer,
                 offset: 0,
-                size: None,
+                size: NonZeroU64::new(size_of::<NodeUniform>() as u64),
             }),
         }],
     });
 
-    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+    (buffer, group)
+}
+
+/// The layers of `detail::layers` in an array, with mipmaps.
+fn create_detail_texture(device: &Device, queue: &Queue) -> TextureView {
+    let layers = detail::layers();
+    let levels: Vec<_> = layers.into_iter().map(detail::mip_levels).collect();
+
+    let texture = device.create_texture(&TextureDescriptor {
         label: None,
-        layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &[&camera_group_layout],
-            push_constant_ranges: &[],
-        })),
+        size: Extent3d {
+            width: detail::SIZE as u32,
+            height: detail::SIZE as u32,
+            depth_or_array_layers: levels.len() as u32,
+        },
+        mip_level_count: levels[0].len() as u32,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::R8Unorm,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+    });
+
+    for (layer, mips) in levels.iter().enumerate() {
+        for (level, data) in mips.iter().enumerate() {
+            let size = (detail::SIZE >> level) as u32;
+            queue.write_texture(
+                ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: TextureAspect::All,
+                },
+                data,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(size),
+                    rows_per_image: NonZeroU32::new(size),
+                },
+                Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+
+    texture.create_view(&TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2Array),
+        ..Default::default()
+    })
+}
+
+/// Terrain, ocean, prop, sky and shadow pipelines, with a shader built for
+/// the vertex layout. The shadow pipeline takes the second layout.
+fn create_pipelines(
+    device: &Device,
+    [pipeline_layout, shadow_pipeline_layout]: [&PipelineLayout; 2],
+    format: TextureFormat,
+    layout: &VertexLayout,
+) -> [RenderPipeline; 5] {
+    let source = layout.shader_source() + include_str!("shader.wgsl");
+    let shader = device.create_shader_module(&ShaderModuleDescriptor {
+        label: Some("shader.wgsl"),
+        source: ShaderSource::Wgsl(Cow::Owned(source)),
+    });
+
+    [
+        PipelineKind::Terrain,
+        PipelineKind::Ocean,
+        PipelineKind::Prop,
+        PipelineKind::Sky,
+        PipelineKind::Shadow,
+    ]
+    .map(|kind| {
+        let pipeline_layout = match kind {
+            PipelineKind::Shadow => shadow_pipeline_layout,
+            _ => pipeline_layout,
+        };
+        create_pipeline(device, pipeline_layout, &shader, format, layout, kind)
+    })
+}
+
+enum PipelineKind {
+    Terrain,
+    Ocean,
+    Prop,
+    /// A triangle over the whole screen, behind everything else.
+    Sky,
+    /// Terrain depth as seen from the sun, without color.
+    Shadow,
+}
+
+fn create_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    format: TextureFormat,
+    vertex_layout: &VertexLayout,
+    kind: PipelineKind,
+) -> RenderPipeline {
+    let (vertex, fragment, blend, depth_write_enabled) = match kind {
+        PipelineKind::Terrain => ("vertex", Some("fragment"), None, true),
+        PipelineKind::Ocean => (
+            "vertex",
+            Some("ocean_fragment"),
+            Some(BlendState::ALPHA_BLENDING),
+            false,
+        ),
+        PipelineKind::Prop => ("prop_vertex", Some("fragment"), None, true),
+        PipelineKind::Sky => ("sky_vertex", Some("sky_fragment"), None, false),
+        PipelineKind::Shadow => ("shadow_vertex", None, None, true),
+    };
+
+    // Shadows are cast by both sides of the terrain, and pushed away from
+    // the sun a little more on slopes facing away from it, so that the
+    // surface doesn't shadow itself.
+    let (cull_mode, bias) = match kind {
+        PipelineKind::Shadow => (
+            None,
+            DepthBiasState {
+                constant: 2,
+                slope_scale: 2.0,
+                clamp: 0.0,
+            },
+        ),
+        _ => (Some(Face::Back), DepthBiasState::default()),
+    };
+    let targets = [ColorTargetState {
+        format,
+        blend,
+        write_mask: ColorWrites::ALL,
+    }];
+
+    let vertices = VertexBufferLayout {
+        array_stride: vertex_layout.stride,
+        step_mode: VertexStepMode::Vertex,
+        attributes: &vertex_layout.attributes,
+    };
+    let instances = VertexBufferLayout {
+        array_stride: size_of::<InstanceData>() as u64,
+        step_mode: VertexStepMode::Instance,
+        attributes: &INSTANCE_ATTRIBUTES,
+    };
+    let buffers = match kind {
+        PipelineKind::Terrain | PipelineKind::Ocean | PipelineKind::Shadow => vec![vertices],
+        PipelineKind::Prop => vec![vertices, instances],
+        PipelineKind::Sky => Vec::new(),
+    };
+
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: None,
+        layout: Some(layout),
         vertex: VertexState {
-            module: &shader,
-            entry_point: "vertex",
-            buffers: &[VertexBufferLayout {
-                array_stride: size_of::<Vertex>() as u64,
-                step_mode: VertexStepMode::Vertex,
-                attributes: &vertex_attr_array![
-                    0 => Float32x3,
-                    1 => Float32x3,
-                    2 => Float32x3,
-                ],
-            }],
+            module: shader,
+            entry_point: vertex,
+            buffers: &buffers,
         },
         primitive: PrimitiveState {
             topology: PrimitiveTopology::TriangleList,
             strip_index_format: None,
             front_face: FrontFace::Ccw,
-            cull_mode: Some(Face::Back),
+            cull_mode,
             unclipped_depth: false,
             polygon_mode: PolygonMode::Fill,
             conservative: false,
         },
         depth_stencil: Some(DepthStencilState {
             format: TextureFormat::Depth32Float,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::LessEqual,
+            depth_write_enabled,
+            depth_compare: CompareFunction::LessEqual,
             stencil: StencilState::default(),
-            bias: DepthBiasState::default(),
+            bias,
         }),
         multisample: MultisampleState {
             count: 1,
             mask: !0,
             alpha_to_coverage_enabled: false,
         },
-        fragment: Some(FragmentState {
-            module: &shader,
-            entry_point: "fragment",
-            targets: &[format.into()],
+        fragment: fragment.map(|entry_point| FragmentState {
+            module: shader,
+            entry_point,
+            targets: &targets,
         }),
         multiview: None,
-    });
-
-    let depth_buffer = create_depth_buffer(&device, 1, 1);
-
-    State {
-        surface,
-        format,
-        device,
-        queue,
-        pipeline,
-        camera_buffer,
-        camera_group,
-        depth_buffer,
-    }
+    })
 }
 
 async fn init_device(instance: &Instance, surface: &Surface) -> InitDevice {
@@ -188,14 +687,155 @@ impl State {
         self.depth_buffer = create_depth_buffer(&self.device, width, height);
     }
 
-    pub fn render(&self, orbiter: &Orbiter, meshes: &[Rc<Mesh>]) {
+    /// Takes on the vertex layout, detail settings, atmosphere, shadow
+    /// settings and props of a planet.
+    /// Meshes created before have to be created again.
+    pub fn reload(&mut self, planet: &Planet) {
+        self.queue.write_buffer(
+            &self.detail_buffer,
+            0,
+            cast_slice(&[DetailUniform::new(planet)]),
+        );
+        self.detail_period = detail_period(planet);
+        self.atmosphere = AtmosphereUniform::new(planet);
+
+        self.shadows_enabled = planet.shadows.enabled;
+        if planet.shadows.quality != self.shadow_maps.quality {
+            self.shadow_maps = ShadowMaps::new(
+                &self.device,
+                &self.shadow_layout,
+                &self.shadow_buffer,
+                planet.shadows.quality,
+            );
+        }
+
+        let layout = VertexLayout::new(&planet.attributes);
+        if layout.extra != self.layout.extra {
+            [
+                self.pipeline,
+                self.ocean_pipeline,
+                self.prop_pipeline,
+                self.sky_pipeline,
+                self.shadow_pipeline,
+            ] = create_pipelines(
+                &self.device,
+                [&self.pipeline_layout, &self.shadow_pipeline_layout],
+                self.format,
+                &layout,
+            );
+            self.layout = layout;
+        }
+
+        self.props = self.create_props(planet);
+    }
+
+    pub fn toggle_shadows(&mut self) {
+        self.shadows_enabled = !self.shadows_enabled;
+    }
+
+    fn create_props(&self, planet: &Planet) -> Vec<Mesh> {
+        planet
+            .scatter
+            .iter()
+            .map(|scatter| self.create_mesh(&scatter::prototype(scatter.shape, scatter.color)))
+            .collect()
+    }
+
+    /// Draws everything relative to the camera, with each mesh moved by the
+    /// offset from the camera to its origin, so that the GPU only ever sees
+    /// small numbers.
+    pub fn render(
+        &mut self,
+        orbiter: &Orbiter,
+        sun: &Sun,
+        meshes: &[Rc<Mesh>],
+        oceans: &[Rc<Mesh>],
+        props: &[Rc<Instances>],
+    ) {
         self.queue
             .write_buffer(&self.camera_buffer, 0, cast_slice(&[orbiter.matrices()]));
 
+        let camera = orbiter.position();
+        self.queue.write_buffer(
+            &self.atmosphere_buffer,
+            0,
+            cast_slice(&[self.atmosphere.seen_from(camera)]),
+        );
+        self.queue
+            .write_buffer(&self.sun_buffer, 0, cast_slice(&[SunUniform::new(sun)]));
+
+        // Nodes in the order they are drawn.
+        let origins: Vec<_> = meshes
+            .iter()
+            .map(|mesh| mesh.origin)
+            .chain(props.iter().map(|instances| instances.origin))
+            .chain(oceans.iter().map(|mesh| mesh.origin))
+            .collect();
+
+        if origins.len() > self.node_capacity {
+            self.node_capacity = origins.len().next_power_of_two();
+            (self.node_buffer, self.node_group) =
+                create_node_group(&self.device, &self.node_layout, self.node_capacity);
+        }
+
+        let mut nodes = vec![0; origins.len() * NODE_STRIDE];
+        for (bytes, &origin) in nodes.chunks_exact_mut(NODE_STRIDE).zip(&origins) {
+            let node = NodeUniform::new(origin, camera, self.detail_period);
+            bytes[..size_of::<NodeUniform>()].copy_from_slice(cast_slice(&[node]));
+        }
+        self.queue.write_buffer(&self.node_buffer, 0, &nodes);
+        let mut offsets = (0..).map(|i| (i * NODE_STRIDE) as u32);
+
+        let quality = self.shadow_maps.quality;
+        let cascades = self
+            .shadows_enabled
+            .then(|| Cascades::fit(orbiter, sun.direction(), quality.resolution()));
+        let shadows = ShadowUniform::new(cascades.as_ref(), orbiter.view_direction(), quality);
+        self.queue
+            .write_buffer(&self.shadow_buffer, 0, cast_slice(&[shadows]));
+
         let frame = self.surface.get_current_texture().unwrap();
         let view = frame.texture.create_view(&Default::default());
         let mut encoder = self.device.create_command_encoder(&Default::default());
 
+        if let Some(cascades) = &cascades {
+            let mut casters = vec![0; CASCADES * NODE_STRIDE];
+            for (bytes, matrix) in casters
+                .chunks_exact_mut(NODE_STRIDE)
+                .zip(&cascades.matrices)
+            {
+                let matrix: &[f32; 16] = matrix.as_ref();
+                bytes[..size_of::<Matrix4<f32>>()].copy_from_slice(cast_slice(matrix));
+            }
+            self.queue.write_buffer(&self.caster_buffer, 0, &casters);
+
+            // Only terrain casts shadows, with the same node uniforms as in
+            // the main pass.
+            for (cascade, layer) in self.shadow_maps.layers.iter().enumerate() {
+                let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                    label: None,
+                    color_attachments: &[],
+                    depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                        view: layer,
+                        depth_ops: Some(Operations {
+                            load: LoadOp::Clear(1.0),
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+
+                pass.set_pipeline(&self.shadow_pipeline);
+                pass.set_bind_group(0, &self.camera_group, &[]);
+                pass.set_bind_group(1, &self.detail_group, &[]);
+                pass.set_bind_group(3, &self.caster_group, &[(cascade * NODE_STRIDE) as u32]);
+                for (i, mesh) in meshes.iter().enumerate() {
+                    pass.set_bind_group(2, &self.node_group, &[(i * NODE_STRIDE) as u32]);
+                    mesh.draw(&mut pass);
+                }
+            }
+        }
+
         {
             let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
                 label: None,
@@ -217,12 +857,37 @@ impl State {
                 }),
             });
 
-            pass.set_pipeline(&self.pipeline);
             pass.set_bind_group(0, &self.camera_group, &[]);
+            pass.set_bind_group(1, &self.detail_group, &[]);
+            pass.set_bind_group(3, &self.shadow_maps.group, &[]);
+
+            pass.set_pipeline(&self.pipeline);
             for mesh in meshes {
-                pass.set_vertex_buffer(0, mesh.vertices.slice(..));
-                pass.set_index_buffer(mesh.triangles.slice(..), IndexFormat::Uint16);
-                pass.draw_indexed(0..mesh.triangles_len * 3, 0, 0..1);
+                pass.set_bind_group(2, &self.node_group, &[offsets.next().unwrap()]);
+                mesh.draw(&mut pass);
+            }
+
+            pass.set_pipeline(&self.prop_pipeline);
+            for instances in props {
+                pass.set_bind_group(2, &self.node_group, &[offsets.next().unwrap()]);
+                for (kind, range) in &instances.ranges {
+                    if let Some(mesh) = self.props.get(*kind) {
+                        mesh.draw_instanced(&mut pass, &instances.buffer, range.clone());
+                    }
+                }
+            }
+
+            // Only where nothing else was drawn. The sky doesn't read the node
+            // uniform, but the layout it shares with the other pipelines
+            // needs it bound.
+            pass.set_pipeline(&self.sky_pipeline);
+            pass.set_bind_group(2, &self.node_group, &[0]);
+            pass.draw(0..3, 0..1);
+
+            pass.set_pipeline(&self.ocean_pipeline);
+            for mesh in oceans {
+                pass.set_bind_group(2, &self.node_group, &[offsets.next().unwrap()]);
+                mesh.draw(&mut pass);
             }
         }
 
@@ -230,39 +895,528 @@ impl State {
         frame.present();
     }
 
-    pub fn create_mesh(&self, vertices: &[Vertex], triangles: &[[u16; 3]]) -> Mesh {
+    pub fn create_mesh(&self, data: &MeshData) -> Mesh {
         Mesh {
+            origin: data.origin,
             vertices: self.device.create_buffer_init(&BufferInitDescriptor {
                 label: None,
-                contents: cast_slice(vertices),
+                contents: &self.layout.pack(&data.vertices),
                 usage: BufferUsages::VERTEX,
             }),
             triangles: self.device.create_buffer_init(&BufferInitDescriptor {
                 label: None,
-                contents: cast_slice(triangles),
+                contents: cast_slice(&data.triangles),
                 usage: BufferUsages::INDEX,
             }),
-            triangles_len: triangles.len() as u32,
+            triangles_len: data.triangles.len() as u32,
+        }
+    }
+
+    /// Expects instances of the same kind next to each other. They are
+    /// stored relative to the first one.
+    pub fn create_instances(&self, instances: &[scatter::Instance]) -> Instances {
+        let origin = instances
+            .first()
+            .map_or_else(Vector3::zero, |instance| instance.position);
+        let data: Vec<_> = instances
+            .iter()
+            .map(|instance| InstanceData {
+                position: (instance.position - origin).cast().unwrap(),
+                right: instance.right,
+                up: instance.up,
+                forward: instance.forward,
+            })
+            .collect();
+
+        let mut ranges: Vec<(usize, Range<u32>)> = Vec::new();
+        for (i, instance) in instances.iter().enumerate() {
+            match ranges.last_mut() {
+                Some((kind, range)) if *kind == instance.kind => range.end = i as u32 + 1,
+                _ => ranges.push((instance.kind, i as u32..i as u32 + 1)),
+            }
+        }
+
+        Instances {
+            origin,
+            buffer: self.device.create_buffer_init(&BufferInitDescriptor {
+                label: None,
+                contents: cast_slice(&data),
+                usage: BufferUsages::VERTEX,
+            }),
+            ranges,
         }
     }
 }
 
 pub struct Mesh {
+    /// What the vertex positions are relative to.
+    origin: Vector3<f64>,
     vertices: Buffer,
     triangles: Buffer,
     triangles_len: u32,
 }
 
+impl Mesh {
+    fn draw<'a>(&'a self, pass: &mut RenderPass<'a>) {
+        pass.set_vertex_buffer(0, self.vertices.slice(..));
+        pass.set_index_buffer(self.triangles.slice(..), IndexFormat::Uint32);
+        pass.draw_indexed(0..self.triangles_len * 3, 0, 0..1);
+    }
+
+    fn draw_instanced<'a>(
+        &'a self,
+        pass: &mut RenderPass<'a>,
+        instances: &'a Buffer,
+        range: Range<u32>,
+    ) {
+        pass.set_vertex_buffer(0, self.vertices.slice(..));
+        pass.set_vertex_buffer(1, instances.slice(..));
+        pass.set_index_buffer(self.triangles.slice(..), IndexFormat::Uint32);
+        pass.draw_indexed(0..self.triangles_len * 3, 0, range);
+    }
+}
+
+/// Placed props, drawn with the mesh of their kind.
+pub struct Instances {
+    origin: Vector3<f64>,
+    buffer: Buffer,
+    /// Instances of each `Scatter` entry.
+    ranges: Vec<(usize, Range<u32>)>,
+}
+
+/// After the locations the vertex layout can take.
+const INSTANCE_ATTRIBUTES: [VertexAttribute; 4] = vertex_attr_array![
+    9 => Float32x3,
+    10 => Float32x3,
+    11 => Float32x3,
+    12 => Float32x3,
+];
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct InstanceData {
+    position: Vector3<f32>,
+    right: Vector3<f32>,
+    up: Vector3<f32>,
+    forward: Vector3<f32>,
+}
+
+unsafe impl Zeroable for InstanceData {}
+unsafe impl Pod for InstanceData {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct NodeUniform {
+    /// From the camera to the origin of the node.
+    offset: [f32; 4],
+    /// From the planet center to the origin, only as precise as f32.
+    center: [f32; 4],
+    /// The origin wrapped into one period of the detail textures.
+    detail_origin: [f32; 4],
+}
+
+unsafe impl Zeroable for NodeUniform {}
+unsafe impl Pod for NodeUniform {}
+
+impl NodeUniform {
+    fn new(origin: Vector3<f64>, camera: Vector3<f64>, detail_period: f64) -> Self {
+        let small = |v: Vector3<f64>| v.cast::<f32>().unwrap().extend(0.0).into();
+        Self {
+            offset: small(origin - camera),
+            center: small(origin),
+            detail_origin: small(origin.map(|e| e.rem_euclid(detail_period))),
+        }
+    }
+}
+
+/// The shader layers the detail textures at two scales, the finer one 5.3
+/// times the coarser, so both repeat after 10 tiles of the coarse one.
+fn detail_period(planet: &Planet) -> f64 {
+    const TILES: f64 = 10.0;
+    let scale = planet.detail.as_ref().map_or(1.0, |detail| detail.scale);
+    TILES / scale as f64
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DetailUniform {
+    scale: f32,
+    strength: f32,
+    radius: f32,
+    padding: f32,
+    rock_slope: [f32; 2],
+    rock_elevation: [f32; 2],
+}
+
+unsafe impl Zeroable for DetailUniform {}
+unsafe impl Pod for DetailUniform {}
+
+impl DetailUniform {
+    fn new(planet: &Planet) -> Self {
+        let detail = planet.detail.as_ref();
+        Self {
+            scale: detail.map_or(1.0, |detail| detail.scale),
+            strength: detail.map_or(0.0, |detail| detail.strength),
+            radius: planet.radius,
+            padding: 0.0,
+            rock_slope: detail.map_or([0.0, 1.0], |detail| detail.rock_slope.into()),
+            rock_elevation: detail.map_or([0.0, 1.0], |detail| detail.rock_elevation.into()),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct AtmosphereUniform {
+    /// From the camera to the planet center.
+    center: [f32; 3],
+    planet_radius: f32,
+    rayleigh: [f32; 3],
+    rayleigh_height: f32,
+    /// Equal to `planet_radius` without an atmosphere.
+    radius: f32,
+    mie: f32,
+    mie_height: f32,
+    mie_anisotropy: f32,
+    intensity: f32,
+    padding: [f32; 3],
+}
+
+unsafe impl Zeroable for AtmosphereUniform {}
+unsafe impl Pod for AtmosphereUniform {}
+
+impl AtmosphereUniform {
+    fn new(planet: &Planet) -> Self {
+        let atmosphere = planet.atmosphere.as_ref();
+        Self {
+            center: [0.0; 3],
+            planet_radius: planet.radius,
+            rayleigh: atmosphere.map_or([0.0; 3], |atmosphere| atmosphere.rayleigh),
+            rayleigh_height: atmosphere.map_or(1.0, |atmosphere| atmosphere.rayleigh_height),
+            radius: atmosphere.map_or(planet.radius, |atmosphere| atmosphere.radius),
+            mie: atmosphere.map_or(0.0, |atmosphere| atmosphere.mie),
+            mie_height: atmosphere.map_or(1.0, |atmosphere| atmosphere.mie_height),
+            mie_anisotropy: atmosphere.map_or(0.0, |atmosphere| atmosphere.mie_anisotropy),
+            intensity: atmosphere.map_or(0.0, |atmosphere| atmosphere.intensity),
+            padding: [0.0; 3],
+        }
+    }
+
+    fn seen_from(self, camera: Vector3<f64>) -> Self {
+        Self {
+            center: (-camera).cast::<f32>().unwrap().into(),
+            ..self
+        }
+    }
+}
+
+/// The shadow map of each cascade, as layers of one texture.
+struct ShadowMaps {
+    quality: ShadowQuality,
+    /// For rendering into each layer.
+    layers: Vec<TextureView>,
+    /// The `ShadowUniform`, the maps and a sampler that compares with them.
+    group: BindGroup,
+}
+
+impl ShadowMaps {
+    fn new(
+        device: &Device,
+        layout: &BindGroupLayout,
+        buffer: &Buffer,
+        quality: ShadowQuality,
+    ) -> Self {
+        let resolution = quality.resolution();
+        let texture = device.create_texture(&TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: CASCADES as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Depth32Float,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+
+        let layers = (0..CASCADES as u32)
+            .map(|layer| {
+                texture.create_view(&TextureViewDescriptor {
+                    dimension: Some(TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let maps = texture.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        // Linear filtering blends the comparisons of the nearest 2 x 2
+        // texels, on top of the filtering in the shader.
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            compare: Some(CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&maps),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        Self {
+            quality,
+            layers,
+            group,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ShadowUniform {
+    /// See `shadow::Cascades`.
+    cascades: [[[f32; 4]; 4]; CASCADES],
+    splits: [f32; CASCADES],
+    texel_sizes: [f32; CASCADES],
+    /// The direction the camera looks in, which `splits` are along.
+    forward: [f32; 3],
+    /// Texels on each side to filter over, or -1 with shadows off.
+    filter: i32,
+}
+
+unsafe impl Zeroable for ShadowUniform {}
+unsafe impl Pod for ShadowUniform {}
+
+impl ShadowUniform {
+    fn new(cascades: Option<&Cascades>, forward: Vector3<f32>, quality: ShadowQuality) -> Self {
+        match cascades {
+            Some(cascades) => Self {
+                cascades: cascades.matrices.map(Into::into),
+                splits: cascades.splits,
+                texel_sizes: cascades.texel_sizes,
+                forward: forward.into(),
+                filter: quality.filter_radius(),
+            },
+            None => Self {
+                filter: -1,
+                ..Self::zeroed()
+            },
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
+struct SunUniform {
+    direction: [f32; 3],
+    ambient: f32,
+}
+
+unsafe impl Zeroable for SunUniform {}
+unsafe impl Pod for SunUniform {}
+
+impl SunUniform {
+    fn new(sun: &Sun) -> Self {
+        Self {
+            direction: sun.direction().into(),
+            ambient: sun.ambient(),
+        }
+    }
+}
+
+/// Mesh contents on the CPU side.
+pub struct MeshData {
+    /// World position the vertex positions are relative to.
+    pub origin: Vector3<f64>,
+    pub vertices: Vec<Vertex>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+/// Vertex flag set on terrain below sea level.
+pub const SEABED: u32 = 1;
+
+/// Everything generation knows about a vertex. Only the attributes in the
+/// `VertexLayout` are uploaded.
+#[derive(Clone, Copy)]
 pub struct Vertex {
     pub position: Vector3<f32>,
     pub normal: Vector3<f32>,
     pub color: Vector3<f32>,
+    pub biome: u32,
+    pub flags: u32,
+    /// Position on the cube face, between 0 and 1.
+    pub uv: Vector2<f32>,
+    /// Along increasing `uv.x`, with the handedness of `uv.y` in `w`.
+    pub tangent: Vector4<f32>,
+    /// Height above the planet radius.
+    pub elevation: f32,
+    /// 0 on flat ground, 1 on vertical cliffs.
+    pub slope: f32,
+}
+
+/// The attributes every vertex has, then the extra ones chosen by the planet
+/// in the order given, at consecutive shader locations.
+struct VertexLayout {
+    extra: Vec<Attribute>,
+    attributes: Vec<VertexAttribute>,
+    stride: u64,
+}
+
+impl VertexLayout {
+    const BASE: [VertexFormat; 5] = [
+        VertexFormat::Float32x3,
+        VertexFormat::Float32x3,
+        VertexFormat::Float32x3,
+        VertexFormat::Uint32,
+        VertexFormat::Uint32,
+    ];
+
+    fn new(attributes: &[Attribute]) -> Self {
+        let mut extra = Vec::new();
+        for &attribute in attributes {
+            if !extra.contains(&attribute) {
+                extra.push(attribute);
+            }
+        }
+
+        let formats = Self::BASE
+            .into_iter()
+            .chain(extra.iter().map(|attribute| attribute.format()));
+        let mut stride = 0;
+        let attributes = formats
+            .enumerate()
+            .map(|(location, format)| {
+                let attribute = VertexAttribute {
+                    format,
+                    offset: stride,
+                    shader_location: location as u32,
+                };
+                stride += format.size();
+                attribute
+            })
+            .collect();
+
+        Self {
+            extra,
+            attributes,
+            stride,
+        }
+    }
+
+    fn pack(&self, vertices: &[Vertex]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(vertices.len() * self.stride as usize);
+        let floats = |bytes: &mut Vec<u8>, values: &[f32]| {
+            bytes.extend(values.iter().flat_map(|value| value.to_ne_bytes()))
+        };
+
+        for vertex in vertices {
+            floats(&mut bytes, AsRef::<[f32; 3]>::as_ref(&vertex.position));
+            floats(&mut bytes, AsRef::<[f32; 3]>::as_ref(&vertex.normal));
+            floats(&mut bytes, AsRef::<[f32; 3]>::as_ref(&vertex.color));
+            bytes.extend(vertex.biome.to_ne_bytes());
+            bytes.extend(vertex.flags.to_ne_bytes());
+
+            for attribute in &self.extra {
+                match attribute {
+                    Attribute::Uv => floats(&mut bytes, AsRef::<[f32; 2]>::as_ref(&vertex.uv)),
+                    Attribute::Tangent => {
+                        floats(&mut bytes, AsRef::<[f32; 4]>::as_ref(&vertex.tangent))
+                    }
+                    Attribute::Elevation => floats(&mut bytes, &[vertex.elevation]),
+                    Attribute::Slope => floats(&mut bytes, &[vertex.slope]),
+                }
+            }
+        }
+
+        bytes
+    }
+
+    /// WGSL for the `Attribs` vertex input, and an `extras` function that
+    /// reads the extra attributes from it or fills in ones left out, given
+    /// the direction away from the planet center.
+    fn shader_source(&self) -> String {
+        let mut fields = String::new();
+        let mut attribs = String::from(concat!(
+            "struct Attribs {\n",
+            "    [[location(0)]] position: vec3<f32>;\n",
+            "    [[location(1)]] normal: vec3<f32>;\n",
+            "    [[location(2)]] color: vec3<f32>;\n",
+            "    [[location(3)]] biome: u32;\n",
+            "    [[location(4)]] flags: u32;\n",
+        ));
+        let mut extras = String::from("fn extras(attribs: Attribs, up: vec3<f32>) -> Extras {\n");
+        extras += "    var extras: Extras;\n";
+
+        for attribute in [
+            Attribute::Uv,
+            Attribute::Tangent,
+            Attribute::Elevation,
+            Attribute::Slope,
+        ] {
+            let (name, ty, default) = attribute.wgsl();
+            fields += &format!("    {}: {};\n", name, ty);
+            match self.extra.iter().position(|&a| a == attribute) {
+                Some(i) => {
+                    let location = Self::BASE.len() + i;
+                    attribs += &format!("    [[location({})]] {}: {};\n", location, name, ty);
+                    extras += &format!("    extras.{0} = attribs.{0};\n", name);
+                }
+                None => extras += &format!("    extras.{} = {};\n", name, default),
+            }
+        }
+
+        format!(
+            "struct Extras {{\n{}}};\n\n{}}};\n\n{}    return extras;\n}}\n\n",
+            fields, attribs, extras
+        )
+    }
 }
 
-unsafe impl Zeroable for Vertex {}
-unsafe impl Pod for Vertex {}
+impl Attribute {
+    fn format(self) -> VertexFormat {
+        match self {
+            Self::Uv => VertexFormat::Float32x2,
+            Self::Tangent => VertexFormat::Float32x4,
+            Self::Elevation | Self::Slope => VertexFormat::Float32,
+        }
+    }
+
+    /// Name, type and the value used when the attribute is left out.
+    fn wgsl(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            Self::Uv => ("uv", "vec2<f32>", "vec2<f32>(0.0)"),
+            Self::Tangent => ("tangent", "vec4<f32>", "vec4<f32>(0.0, 0.0, 0.0, 1.0)"),
+            Self::Elevation => ("elevation", "f32", "0.0"),
+            Self::Slope => ("slope", "f32", "1.0 - max(dot(attribs.normal, up), 0.0)"),
+        }
+    }
+}
 
 fn create_depth_buffer(device: &Device, width: u32, height: u32) -> TextureView {
     let size = Extent3d {
@@ -0,0 +1,220 @@
+use cgmath::{InnerSpace, Vector2, Vector3};
+
+/// Error of every grid point for a right-triangulated irregular network over
+/// a square grid of `size` = 2^n + 1 points. A triangle needs to be split
+/// when the error at the middle of its long edge is over the limit, and the
+/// error there covers every grid point inside the triangles on either side.
+pub struct Errors {
+    size: usize,
+    errors: Vec<f32>,
+    /// Largest distance between the grid and any triangle in the hierarchy,
+    /// so none need to be split when `max_error` is at least this.
+    pub max: f32,
+}
+
+impl Errors {
+    /// With `keep_border`, points on the border get an infinite error, so
+    /// that they are always kept and the edges line up with neighbouring
+    /// grids.
+    pub fn new(positions: &[Vector3<f32>], size: usize, keep_border: bool) -> Self {
+        let mut errors = vec![0.0f32; size * size];
+        let mut max = 0.0f32;
+        let last = size - 1;
+        let on_border =
+            |x: usize, y: usize| keep_border && (x == 0 || y == 0 || x == last || y == last);
+
+        let coords = triangle_coords(size);
+        let parents = coords.len() - last * last;
+
+        // Children come after their parents, so this runs from the smallest up.
+        for (i, &[ax, ay, bx, by]) in coords.iter().enumerate().rev() {
+            let (mx, my) = ((ax + bx) / 2, (ay + by) / 2);
+            let (cx, cy) = (mx + my - ay, my + ax - mx);
+            let middle = my * size + mx;
+
+            let error = deviation(positions, size, [[ax, ay], [bx, by], [cx, cy]]);
+            max = max.max(error);
+
+            let error = if on_border(mx, my) {
+                f32::INFINITY
+            } else {
+                error
+            };
+            errors[middle] = errors[middle].max(error);
+
+            if i < parents {
+                let left = ((ay + cy) / 2) * size + (ax + cx) / 2;
+                let right = ((by + cy) / 2) * size + (bx + cx) / 2;
+                errors[middle] = errors[middle].max(errors[left]).max(errors[right]);
+            }
+        }
+
+        Self { size, errors, max }
+    }
+
+    /// Triangles wound like a regular grid, with every point whose error is
+    /// over `max_error` kept.
+    pub fn triangles(&self, max_error: f32) -> Vec<[u32; 3]> {
+        let last = self.size - 1;
+        let mut triangles = Vec::new();
+        self.split([0, 0], [last, last], [last, 0], max_error, &mut triangles);
+        self.split([last, last], [0, 0], [0, last], max_error, &mut triangles);
+        triangles
+    }
+
+    fn split(
+        &self,
+        a: [usize; 2],
+        b: [usize; 2],
+        c: [usize; 2],
+        max_error: f32,
+        triangles: &mut Vec<[u32; 3]>,
+    ) {
+        let m = [(a[0] + b[0]) / 2, (a[1] + b[1]) / 2];
+        let splittable = a[0].abs_diff(c[0]) + a[1].abs_diff(c[1]) > 1;
+
+        if splittable && self.errors[m[1] * self.size + m[0]] > max_error {
+            self.split(c, a, m, max_error, triangles);
+            self.split(b, c, m, max_error, triangles);
+        } else {
+            let index = |p: [usize; 2]| (p[1] * self.size + p[0]) as u32;
+            triangles.push([index(a), index(c), index(b)]);
+        }
+    }
+}
+
+/// Largest distance between a grid point inside the triangle and the point
+/// on the triangle at the same place in the grid.
+fn deviation(positions: &[Vector3<f32>], size: usize, corners: [[usize; 2]; 3]) -> f32 {
+    let [a, b, c] = corners.map(|[x, y]| Vector2::new(x as f32, y as f32));
+    let [pa, pb, pc] = corners.map(|[x, y]| positions[y * size + x]);
+    let area = (b - a).perp_dot(c - a);
+
+    let xs = corners.map(|[x, _]| x);
+    let ys = corners.map(|[_, y]| y);
+    let (x0, x1) = (*xs.iter().min().unwrap(), *xs.iter().max().unwrap());
+    let (y0, y1) = (*ys.iter().min().unwrap(), *ys.iter().max().unwrap());
+
+    let mut max = 0.0f32;
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            let p = Vector2::new(x as f32, y as f32);
+            let u = (c - b).perp_dot(p - b) / area;
+            let v = (a - c).perp_dot(p - c) / area;
+            let w = 1.0 - u - v;
+            if u < 0.0 || v < 0.0 || w < 0.0 {
+                continue;
+            }
+
+            let on_triangle = pa * u + pb * v + pc * w;
+            max = max.max((positions[y * size + x] - on_triangle).magnitude());
+        }
+    }
+    max
+}
+
+/// The two ends of the long edge of every triangle in the hierarchy, parents
+/// before children.
+fn triangle_coords(size: usize) -> Vec<[usize; 4]> {
+    let last = size - 1;
+    let count = last * last * 2 - 2;
+
+    (0..count)
+        .map(|i| {
+            let mut id = i + 2;
+            let (mut ax, mut ay, mut bx, mut by, mut cx, mut cy) = (0, 0, 0, 0, 0, 0);
+            if id & 1 == 1 {
+                (bx, by, cx) = (last, last, last);
+            } else {
+                (ax, ay, cy) = (last, last, last);
+            }
+
+            loop {
+                id >>= 1;
+                if id <= 1 {
+                    break;
+                }
+
+                let (mx, my) = ((ax + bx) / 2, (ay + by) / 2);
+                if id & 1 == 1 {
+                    (bx, by, ax, ay) = (ax, ay, cx, cy);
+                } else {
+                    (ax, ay, bx, by) = (bx, by, cx, cy);
+                }
+                (cx, cy) = (mx, my);
+            }
+
+            [ax, ay, bx, by]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 33;
+
+    fn grid(height: impl Fn(f32, f32) -> f32) -> Vec<Vector3<f32>> {
+        (0..SIZE * SIZE)
+            .map(|i| {
+                let (x, y) = ((i % SIZE) as f32, (i / SIZE) as f32);
+                Vector3::new(x, y, height(x, y))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn flat_grid_is_two_triangles() {
+        let positions = grid(|_, _| 1.0);
+        let errors = Errors::new(&positions, SIZE, false);
+        assert_eq!(errors.max, 0.0);
+        assert_eq!(errors.triangles(0.0).len(), 2);
+
+        // Kept borders are the only points that split the flat grid.
+        let triangles = Errors::new(&positions, SIZE, true).triangles(0.0);
+        let last = SIZE - 1;
+        for i in 0..SIZE * SIZE {
+            let (x, y) = (i % SIZE, i / SIZE);
+            let border = x == 0 || y == 0 || x == last || y == last;
+            assert!(!border || triangles.iter().flatten().any(|&j| j as usize == i));
+        }
+        assert!(triangles.len() < (SIZE - 1) * (SIZE - 1) / 2);
+    }
+
+    #[test]
+    fn bumpy_grid_stays_within_error() {
+        let positions = grid(|x, y| (x * 0.3).sin() * 2.0 + (y * 0.17).cos() * (x * 0.05));
+        let errors = Errors::new(&positions, SIZE, true);
+
+        for max_error in [0.05, 0.2, 1.0] {
+            let triangles = errors.triangles(max_error);
+            assert!(triangles.len() < (SIZE - 1) * (SIZE - 1) * 2);
+
+            for point in &positions {
+                let mut covered = false;
+                for triangle in &triangles {
+                    let [a, b, c] = triangle.map(|i| positions[i as usize]);
+                    let area = (b - a).truncate().perp_dot((c - a).truncate());
+                    let u = (c - b).truncate().perp_dot((point - b).truncate()) / area;
+                    let v = (a - c).truncate().perp_dot((point - c).truncate()) / area;
+                    let w = 1.0 - u - v;
+                    if u.min(v).min(w) < -1e-4 {
+                        continue;
+                    }
+
+                    covered = true;
+                    let height = a.z * u + b.z * v + c.z * w;
+                    assert!(
+                        (point.z - height).abs() <= max_error + 1e-4,
+                        "{} off at {:?} for {}",
+                        point.z - height,
+                        point,
+                        max_error
+                    );
+                }
+                assert!(covered, "{:?}", point);
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use std::{f32::consts::TAU, rc::Rc};
+
+use cgmath::{vec2, vec3, InnerSpace, Vector2, Vector3, Zero};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::{
+    biome::NO_BIOME,
+    gen::{Info, Terrain},
+    planet::{Scatter, Shape},
+    render::{Instances, MeshData, Vertex},
+};
+
+#[derive(Clone, Copy)]
+pub struct Instance {
+    /// Index of the `Scatter` entry in the planet.
+    pub kind: usize,
+    /// On the surface, at the bottom of the prop.
+    pub position: Vector3<f64>,
+    /// Axes of the prop scaled by its size, with `up` pointing away from the
+    /// planet center.
+    pub right: Vector3<f32>,
+    pub up: Vector3<f32>,
+    pub forward: Vector3<f32>,
+}
+
+/// Instances of a quad, kept on the CPU for queries and uploaded for drawing.
+pub struct Props {
+    pub instances: Vec<Instance>,
+    pub buffer: Rc<Instances>,
+}
+
+/// Places the props of every `Scatter` entry whose depth is that of the quad.
+/// Each quad draws from its own random sequence, seeded by the planet seed
+/// and the address of the quad, so the same quad always gets the same props.
+/// `ground` gives the height they stand at, from the position between -1
+/// and 1 across the quad and the exact direction there.
+pub fn place(
+    terrain: &Terrain,
+    rules: &[Scatter],
+    seed: u32,
+    info: &Info,
+    ground: impl Fn(Vector2<f32>, Vector3<f64>) -> f32,
+) -> Vec<Instance> {
+    let depth = (1.0 / info.scale).log2().round() as u32;
+    let cell = ((info.offset + vec2(1.0, 1.0)) / (info.scale * 2.0)).map(|e| e.floor() as u32);
+    let side = info.scale * 2.0 * terrain.radius();
+
+    let mut instances = Vec::new();
+    for (kind, rule) in rules.iter().enumerate() {
+        if rule.depth != depth {
+            continue;
+        }
+
+        let address = [seed, kind as u32, info.facing as u32, depth, cell.x, cell.y];
+        let mut rng = SmallRng::seed_from_u64(hash(address));
+
+        let count = (rule.density * side * side + rng.gen::<f32>()) as u32;
+        for _ in 0..count {
+            // Every candidate takes the same numbers, whether it is kept or not.
+            let uv = vec2(rng.gen::<f32>(), rng.gen::<f32>()) * 2.0 - vec2(1.0, 1.0);
+            let yaw = rng.gen::<f32>() * TAU;
+            let size = rule.size.0 + (rule.size.1 - rule.size.0) * rng.gen::<f32>();
+
+            let coords = info.offset + uv * info.scale;
+            let exact = info
+                .facing
+                .orient(coords.cast::<f64>().unwrap())
+                .normalize();
+            let direction = exact.cast().unwrap();
+            let height = ground(uv, exact);
+            if !within(height, rule.elevation) {
+                continue;
+            }
+
+            let normal = terrain.normal(direction, info.spacing());
+            if !within(1.0 - normal.dot(direction).max(0.0), rule.slope) {
+                continue;
+            }
+
+            if !rule.biomes.is_empty() {
+                let biome = terrain.biome(direction, height);
+                if !biome.is_some_and(|biome| rule.biomes.contains(&biome.name)) {
+                    continue;
+                }
+            }
+
+            let reference = if direction.y.abs() < 0.9 {
+                Vector3::unit_y()
+            } else {
+                Vector3::unit_x()
+            };
+            let right = direction.cross(reference).normalize();
+            let forward = right.cross(direction);
+            let right = right * yaw.cos() + forward * yaw.sin();
+
+            instances.push(Instance {
+                kind,
+                position: exact * (terrain.radius() as f64 + height as f64),
+                right: right * size,
+                up: direction * size,
+                forward: right.cross(direction) * size,
+            });
+        }
+    }
+
+    instances
+}
+
+fn within(value: f32, (min, max): (f32, f32)) -> bool {
+    (min..=max).contains(&value)
+}
+
+fn hash(values: [u32; 6]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |h: u64, &value| {
+        let mut z = (h ^ value as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    })
+}
+
+/// Flat-shaded mesh of a prop, about one unit tall, standing on the origin
+/// with y up.
+pub fn prototype(shape: Shape, color: [f32; 3]) -> MeshData {
+    let mut mesh = MeshData {
+        origin: Vector3::zero(),
+        vertices: Vec::new(),
+        triangles: Vec::new(),
+    };
+
+    match shape {
+        Shape::Rock => {
+            let ring: Vec<_> = (0..6)
+                .map(|i| {
+                    let angle = i as f32 / 6.0 * TAU;
+                    let radius = if i % 2 == 0 { 0.5 } else { 0.4 };
+                    vec3(angle.cos() * radius, 0.15, angle.sin() * radius)
+                })
+                .collect();
+            let (top, bottom) = (vec3(0.05, 0.6, 0.0), vec3(0.0, -0.3, 0.0));
+            let center = vec3(0.0, 0.15, 0.0);
+
+            for i in 0..6 {
+                let (a, b) = (ring[i], ring[(i + 1) % 6]);
+                push_triangle(&mut mesh, [top, a, b], center, color);
+                push_triangle(&mut mesh, [bottom, a, b], center, color);
+            }
+        }
+        Shape::Tree => {
+            let ring = |radius: f32, y: f32| -> Vec<_> {
+                (0..8)
+                    .map(|i| {
+                        let angle = i as f32 / 8.0 * TAU;
+                        vec3(angle.cos() * radius, y, angle.sin() * radius)
+                    })
+                    .collect()
+            };
+            let trunk = [0.0, 0.3].map(|y| ring(0.06, y));
+            let crown = ring(0.35, 0.25);
+            let apex = vec3(0.0, 1.0, 0.0);
+            let trunk_color = [0.3, 0.2, 0.1];
+
+            for i in 0..8 {
+                let j = (i + 1) % 8;
+                let center = vec3(0.0, 0.15, 0.0);
+                push_triangle(
+                    &mut mesh,
+                    [trunk[0][i], trunk[0][j], trunk[1][j]],
+                    center,
+                    trunk_color,
+                );
+                push_triangle(
+                    &mut mesh,
+                    [trunk[0][i], trunk[1][j], trunk[1][i]],
+                    center,
+                    trunk_color,
+                );
+
+                let center = vec3(0.0, 0.5, 0.0);
+                push_triangle(&mut mesh, [apex, crown[i], crown[j]], center, color);
+                push_triangle(
+                    &mut mesh,
+                    [crown[i], crown[j], vec3(0.0, 0.25, 0.0)],
+                    center,
+                    color,
+                );
+            }
+        }
+    }
+
+    mesh
+}
+
+/// Adds a triangle facing away from `center`.
+fn push_triangle(
+    mesh: &mut MeshData,
+    corners: [Vector3<f32>; 3],
+    center: Vector3<f32>,
+    color: [f32; 3],
+) {
+    let [a, mut b, mut c] = corners;
+    let mut normal = (b - a).cross(c - a).normalize();
+    if normal.dot(a - center) < 0.0 {
+        std::mem::swap(&mut b, &mut c);
+        normal = -normal;
+    }
+
+    let first = mesh.vertices.len() as u32;
+    for position in [a, b, c] {
+        mesh.vertices.push(Vertex {
+            position,
+            normal,
+            color: color.into(),
+            biome: NO_BIOME,
+            flags: 0,
+            uv: vec2(0.0, 0.0),
+            tangent: Zero::zero(),
+            elevation: 0.0,
+            slope: 0.0,
+        });
+    }
+    mesh.triangles.push([first, first + 1, first + 2]);
+}
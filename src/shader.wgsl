@@ -1,13 +1,5 @@
-struct Attribs {
-    [[location(0)]]
-    position: vec3<f32>;
-
-    [[location(1)]]
-    normal: vec3<f32>;
-
-    [[location(2)]]
-    color: vec3<f32>;
-};
+// `Attribs`, `Extras` and `fn extras` are generated in front of this file
+// by `render::VertexLayout`, for the attributes the planet asks for.
 
 struct Vertex {
     [[builtin(position)]]
@@ -16,31 +8,174 @@ struct Vertex {
     [[location(0)]]
     normal: vec3<f32>;
 
+    // From the camera, which is at the origin for the whole pipeline.
     [[location(1)]]
-    world_position: vec3<f32>;
+    relative_position: vec3<f32>;
 
+    // From the planet center, only roughly on large planets.
     [[location(2)]]
-    camera_position: vec3<f32>;
+    planet_position: vec3<f32>;
 
     [[location(3)]]
     color: vec3<f32>;
+
+    [[location(4)]]
+    uv: vec2<f32>;
+
+    [[location(5)]]
+    tangent: vec4<f32>;
+
+    [[location(6)]]
+    elevation: f32;
+
+    [[location(7)]]
+    slope: f32;
+
+    // For the detail textures, which repeat before it gets large.
+    [[location(8)]]
+    detail_position: vec3<f32>;
 };
 
 struct Camera {
     world_to_clip: mat4x4<f32>;
     view_to_world: mat4x4<f32>;
+    clip_to_world: mat4x4<f32>;
 };
 
 [[group(0), binding(0)]]
 var<uniform> camera: Camera;
 
+// See `render::AtmosphereUniform`.
+struct Atmosphere {
+    center: vec3<f32>;
+    planet_radius: f32;
+    rayleigh: vec3<f32>;
+    rayleigh_height: f32;
+    radius: f32;
+    mie: f32;
+    mie_height: f32;
+    mie_anisotropy: f32;
+    intensity: f32;
+};
+
+[[group(0), binding(1)]]
+var<uniform> atmosphere: Atmosphere;
+
+struct Sun {
+    direction: vec3<f32>;
+    ambient: f32;
+};
+
+[[group(0), binding(2)]]
+var<uniform> sun: Sun;
+
+struct Detail {
+    scale: f32;
+    strength: f32;
+    radius: f32;
+    padding: f32;
+    rock_slope: vec2<f32>;
+    rock_elevation: vec2<f32>;
+};
+
+// Ground in layer 0, rock in layer 1.
+[[group(1), binding(0)]]
+var detail_texture: texture_2d_array<f32>;
+
+[[group(1), binding(1)]]
+var detail_sampler: sampler;
+
+[[group(1), binding(2)]]
+var<uniform> detail: Detail;
+
+// The origin of the mesh being drawn, see `render::NodeUniform`.
+struct Node {
+    offset: vec3<f32>;
+    center: vec3<f32>;
+    detail_origin: vec3<f32>;
+};
+
+[[group(2), binding(0)]]
+var<uniform> node: Node;
+
+// See `render::ShadowUniform`.
+struct Shadows {
+    cascades: array<mat4x4<f32>, 4>;
+    splits: vec4<f32>;
+    texel_sizes: vec4<f32>;
+    forward: vec3<f32>;
+    filter: i32;
+};
+
+[[group(3), binding(0)]]
+var<uniform> shadows: Shadows;
+
+[[group(3), binding(1)]]
+var shadow_maps: texture_depth_2d_array;
+
+[[group(3), binding(2)]]
+var shadow_sampler: sampler_comparison;
+
+// The cascade being rendered, only bound in place of the shadow maps while
+// rendering them.
+struct Caster {
+    world_to_clip: mat4x4<f32>;
+};
+
+[[group(3), binding(3)]]
+var<uniform> caster: Caster;
+
+// Fills in the positions of a vertex at `position` from the node origin.
+fn place(vertex: ptr<function, Vertex>, position: vec3<f32>) {
+    let relative = node.offset + position;
+    (*vertex).position = camera.world_to_clip * vec4<f32>(relative, 1.0);
+    (*vertex).relative_position = relative;
+    (*vertex).planet_position = node.center + position;
+    (*vertex).detail_position = node.detail_origin + position;
+}
+
 [[stage(vertex)]]
 fn vertex(attribs: Attribs) -> Vertex {
     var vertex: Vertex;
-    vertex.position = camera.world_to_clip * vec4<f32>(attribs.position, 1.0);
+    place(&vertex, attribs.position);
     vertex.normal = attribs.normal;
-    vertex.camera_position = (camera.view_to_world * vec4<f32>(0.0, 0.0, 0.0, 1.0)).xyz;
-    vertex.world_position = attribs.position;
+    vertex.color = attribs.color;
+
+    let extra = extras(attribs, normalize(vertex.planet_position));
+    vertex.uv = extra.uv;
+    vertex.tangent = extra.tangent;
+    vertex.elevation = extra.elevation;
+    vertex.slope = extra.slope;
+    return vertex;
+}
+
+[[stage(vertex)]]
+fn shadow_vertex(attribs: Attribs) -> [[builtin(position)]] vec4<f32> {
+    return caster.world_to_clip * vec4<f32>(node.offset + attribs.position, 1.0);
+}
+
+// Axes scaled by the size of the prop, see `scatter::Instance`.
+struct Instance {
+    [[location(9)]]
+    position: vec3<f32>;
+
+    [[location(10)]]
+    right: vec3<f32>;
+
+    [[location(11)]]
+    up: vec3<f32>;
+
+    [[location(12)]]
+    forward: vec3<f32>;
+};
+
+[[stage(vertex)]]
+fn prop_vertex(attribs: Attribs, instance: Instance) -> Vertex {
+    let model = mat3x3<f32>(instance.right, instance.up, instance.forward);
+
+    var vertex: Vertex;
+    place(&vertex, instance.position + model * attribs.position);
+    vertex.normal = normalize(model * attribs.normal);
     vertex.color = attribs.color;
     return vertex;
 }
@@ -50,12 +185,250 @@ struct Fragment {
     color: vec4<f32>;
 };
 
+// Projected along each axis and blended by the normal. It only depends on
+// world position, wrapped the same way for every node, so it lines up across
+// quad and face boundaries.
+fn triplanar(position: vec3<f32>, normal: vec3<f32>, layer: i32) -> f32 {
+    var weights = pow(abs(normal), vec3<f32>(4.0));
+    weights = weights / (weights.x + weights.y + weights.z);
+
+    let x = textureSample(detail_texture, detail_sampler, position.yz, layer).r;
+    let y = textureSample(detail_texture, detail_sampler, position.zx, layer).r;
+    let z = textureSample(detail_texture, detail_sampler, position.xy, layer).r;
+    return x * weights.x + y * weights.y + z * weights.z;
+}
+
+// Two scales at an uneven ratio, so that the tiling doesn't show.
+fn detail_layer(position: vec3<f32>, normal: vec3<f32>, layer: i32) -> f32 {
+    let coarse = triplanar(position * detail.scale, normal, layer);
+    let fine = triplanar(position * detail.scale * 5.3, normal, layer);
+    return (coarse + fine) * 0.5;
+}
+
+// Multiplies the vertex color, averaging 1.
+fn detail_factor(vertex: Vertex) -> f32 {
+    let elevation = length(vertex.planet_position) - detail.radius;
+    let rock = max(
+        smoothStep(detail.rock_slope.x, detail.rock_slope.y, vertex.slope),
+        smoothStep(detail.rock_elevation.x, detail.rock_elevation.y, elevation),
+    );
+
+    let ground_value = detail_layer(vertex.detail_position, vertex.normal, 0);
+    let rock_value = detail_layer(vertex.detail_position, vertex.normal, 1);
+    let value = mix(ground_value, rock_value, rock);
+    return 1.0 + (value * 2.0 - 1.0) * detail.strength;
+}
+
+// Distances along a ray to where it enters and leaves a sphere around the
+// planet center, the first larger than the second if it misses. `origin` is
+// from the planet center.
+fn sphere_hits(origin: vec3<f32>, direction: vec3<f32>, radius: f32) -> vec2<f32> {
+    // From the closest point on the ray, which stays precise when the origin
+    // is far out compared to the size of the sphere.
+    let middle = -dot(origin, direction);
+    let closest = origin + direction * middle;
+    let squared = radius * radius - dot(closest, closest);
+    if (squared < 0.0) {
+        return vec2<f32>(1.0, -1.0);
+    }
+    let half = sqrt(squared);
+    return vec2<f32>(middle - half, middle + half);
+}
+
+// Rayleigh and Mie densities relative to the planet radius, at `position`
+// from the planet center.
+fn air_density(position: vec3<f32>) -> vec2<f32> {
+    let height = max(length(position) - atmosphere.planet_radius, 0.0);
+    return exp(-height / vec2<f32>(atmosphere.rayleigh_height, atmosphere.mie_height));
+}
+
+// Of light through air of the given Rayleigh and Mie densities integrated
+// over distance. Haze absorbs a little on top of what it scatters.
+fn transmittance(depth: vec2<f32>) -> vec3<f32> {
+    return exp(-(atmosphere.rayleigh * depth.x + atmosphere.mie * 1.1 * depth.y));
+}
+
+// Air between `position` and the top of the atmosphere towards the sun.
+fn light_depth(position: vec3<f32>) -> vec2<f32> {
+    let steps = 4;
+    let top = sphere_hits(position, sun.direction, atmosphere.radius).y;
+    let step = max(top, 0.0) / f32(steps);
+    var depth = vec2<f32>(0.0);
+    for (var i = 0; i < steps; i = i + 1) {
+        let sample = position + sun.direction * (f32(i) + 0.5) * step;
+        depth = depth + air_density(sample) * step;
+    }
+    return depth;
+}
+
+// Whether the planet is not in the way of the sun. Points below the radius
+// count as lit, since terrain sticks out on both sides of it.
+fn in_light(position: vec3<f32>) -> bool {
+    let hits = sphere_hits(position, sun.direction, atmosphere.planet_radius);
+    return hits.x > hits.y || hits.x <= 0.0;
+}
+
+fn rayleigh_phase(cos_angle: f32) -> f32 {
+    return 3.0 / (16.0 * 3.14159265) * (1.0 + cos_angle * cos_angle);
+}
+
+// Cornette-Shanks, a Henyey-Greenstein that also falls off to the sides.
+fn mie_phase(cos_angle: f32) -> f32 {
+    let g = atmosphere.mie_anisotropy;
+    let g2 = g * g;
+    let denominator = (2.0 + g2) * pow(1.0 + g2 - 2.0 * g * cos_angle, 1.5);
+    return 3.0 / (8.0 * 3.14159265) * (1.0 - g2) * (1.0 + cos_angle * cos_angle) / denominator;
+}
+
+struct Scattering {
+    // Scattered towards the camera by the air along the ray.
+    light: vec3<f32>;
+    // Of whatever lies at the end of the ray.
+    transmittance: vec3<f32>;
+};
+
+// Single scattering along a ray from the camera, up to `distance` or the
+// edge of the atmosphere, in `steps` samples.
+fn scattering(direction: vec3<f32>, distance: f32, steps: i32) -> Scattering {
+    var result: Scattering;
+    result.light = vec3<f32>(0.0);
+    result.transmittance = vec3<f32>(1.0);
+
+    let origin = -atmosphere.center;
+    let hits = sphere_hits(origin, direction, atmosphere.radius);
+    let start = max(hits.x, 0.0);
+    let end = min(hits.y, distance);
+    if (atmosphere.radius <= atmosphere.planet_radius || end <= start) {
+        return result;
+    }
+
+    let step = (end - start) / f32(steps);
+    var depth = vec2<f32>(0.0);
+    var rayleigh = vec3<f32>(0.0);
+    var mie = vec3<f32>(0.0);
+    for (var i = 0; i < steps; i = i + 1) {
+        let position = origin + direction * (start + (f32(i) + 0.5) * step);
+        let density = air_density(position) * step;
+        depth = depth + density;
+        if (in_light(position)) {
+            let light = transmittance(depth + light_depth(position));
+            rayleigh = rayleigh + light * density.x;
+            mie = mie + light * density.y;
+        }
+    }
+
+    let cos_angle = dot(direction, sun.direction);
+    rayleigh = rayleigh * atmosphere.rayleigh * rayleigh_phase(cos_angle);
+    mie = mie * atmosphere.mie * mie_phase(cos_angle);
+    result.light = (rayleigh + mie) * atmosphere.intensity;
+    result.transmittance = transmittance(depth);
+    return result;
+}
+
+// Haze between the camera and a surface at `relative_position` from it.
+fn aerial_perspective(color: vec3<f32>, relative_position: vec3<f32>) -> vec3<f32> {
+    let distance = length(relative_position);
+    let air = scattering(relative_position / distance, distance, 8);
+    return color * air.transmittance + air.light;
+}
+
+// Sunlight reaching `position`, which is measured from the planet center,
+// reddened by the air it came through on the way from the sun.
+fn sunlight(position: vec3<f32>) -> vec3<f32> {
+    return transmittance(light_depth(position));
+}
+
+// How much of the sun reaches a surface, from the first cascade that covers
+// it. Looked up a little way out along the normal, in proportion to the size
+// of the texels, so that the surface doesn't shadow itself.
+fn shadow(vertex: Vertex) -> f32 {
+    let distance = dot(vertex.relative_position, shadows.forward);
+    var cascade = 4;
+    for (var i = 3; i >= 0; i = i - 1) {
+        if (distance < shadows.splits[i]) {
+            cascade = i;
+        }
+    }
+    if (shadows.filter < 0 || cascade == 4) {
+        return 1.0;
+    }
+
+    let offset = vertex.normal * shadows.texel_sizes[cascade] * 1.5;
+    let clip = shadows.cascades[cascade] * vec4<f32>(vertex.relative_position + offset, 1.0);
+    let uv = vec2<f32>(clip.x, -clip.y) * 0.5 + 0.5;
+    let texel = 1.0 / vec2<f32>(textureDimensions(shadow_maps));
+
+    var lit = 0.0;
+    for (var y = -shadows.filter; y <= shadows.filter; y = y + 1) {
+        for (var x = -shadows.filter; x <= shadows.filter; x = x + 1) {
+            let at = uv + vec2<f32>(f32(x), f32(y)) * texel;
+            lit = lit + textureSampleCompareLevel(shadow_maps, shadow_sampler, at, cascade, clip.z);
+        }
+    }
+    let size = f32(shadows.filter * 2 + 1);
+    return lit / (size * size);
+}
+
+// Lambert shading from the sun, on top of the ambient light.
+fn diffuse(vertex: Vertex) -> vec3<f32> {
+    let lambert = max(dot(sun.direction, vertex.normal), 0.0);
+    return sun.ambient + sunlight(vertex.planet_position) * lambert * shadow(vertex);
+}
+
 [[stage(fragment)]]
 fn fragment(vertex: Vertex) -> Fragment {
-    var light_dir = normalize(vertex.camera_position - vertex.world_position);
-    var light_intensity = max(dot(light_dir, vertex.normal), 0.0);
+    var fragment: Fragment;
+    let color = vertex.color * detail_factor(vertex) * diffuse(vertex);
+    fragment.color = vec4<f32>(aerial_perspective(color, vertex.relative_position), 1.0);
+    return fragment;
+}
+
+// With a Blinn-Phong highlight where the water reflects the sun.
+[[stage(fragment)]]
+fn ocean_fragment(vertex: Vertex) -> Fragment {
+    let view = normalize(-vertex.relative_position);
+    let half = normalize(view + sun.direction);
+    let lit = max(dot(sun.direction, vertex.normal), 0.0);
+    let specular = pow(max(dot(vertex.normal, half), 0.0), 64.0) * sign(lit);
 
     var fragment: Fragment;
-    fragment.color = vec4<f32>(vertex.color * light_intensity, 1.0);
+    let color = vertex.color * diffuse(vertex) + sunlight(vertex.planet_position) * specular;
+    fragment.color = vec4<f32>(aerial_perspective(color, vertex.relative_position), 0.8);
     return fragment;
-}
\ No newline at end of file
+}
+
+struct Sky {
+    [[builtin(position)]]
+    position: vec4<f32>;
+
+    // From the camera, not normalized.
+    [[location(0)]]
+    direction: vec3<f32>;
+};
+
+// One triangle over the whole screen, on the far plane.
+[[stage(vertex)]]
+fn sky_vertex([[builtin(vertex_index)]] index: u32) -> Sky {
+    let clip = vec4<f32>(f32(index & 1u) * 4.0 - 1.0, f32(index >> 1u) * 4.0 - 1.0, 1.0, 1.0);
+    let far = camera.clip_to_world * clip;
+
+    var sky: Sky;
+    sky.position = clip;
+    sky.direction = far.xyz / far.w;
+    return sky;
+}
+
+// The atmosphere up to the planet, or through it where the ray misses.
+[[stage(fragment)]]
+fn sky_fragment(sky: Sky) -> Fragment {
+    let direction = normalize(sky.direction);
+    let ground = sphere_hits(-atmosphere.center, direction, atmosphere.planet_radius);
+    var distance = 3.0e38;
+    if (ground.x <= ground.y && ground.x > 0.0) {
+        distance = ground.x;
+    }
+
+    var fragment: Fragment;
+    fragment.color = vec4<f32>(scattering(direction, distance, 16).light, 1.0);
+    return fragment;
+}
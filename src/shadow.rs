@@ -0,0 +1,107 @@
+use cgmath::{InnerSpace, Matrix, Matrix4, Vector3, Vector4};
+
+use crate::{orbiter::Orbiter, planet::ShadowQuality};
+
+pub const CASCADES: usize = 4;
+
+/// Between 0 for splits evenly spaced along the view and 1 for splits at
+/// the same ratio from one to the next.
+const LOGARITHMIC_SPLITS: f32 = 0.9;
+
+impl ShadowQuality {
+    /// Texels along each side of a shadow map.
+    pub fn resolution(self) -> u32 {
+        match self {
+            Self::Low => 1024,
+            Self::Medium => 2048,
+            Self::High => 3072,
+        }
+    }
+
+    /// Texels on each side of the center one that shadows are averaged over.
+    pub fn filter_radius(self) -> i32 {
+        match self {
+            Self::Low => 0,
+            Self::Medium => 1,
+            Self::High => 2,
+        }
+    }
+}
+
+/// Shadow maps from the sun over consecutive slices of the view, from the
+/// near plane to the far plane.
+pub struct Cascades {
+    /// From world space relative to the camera to the clip space of each
+    /// shadow map.
+    pub matrices: [Matrix4<f32>; CASCADES],
+    /// Distance along the view where each slice ends.
+    pub splits: [f32; CASCADES],
+    /// Size of a texel of each shadow map in world units.
+    pub texel_sizes: [f32; CASCADES],
+}
+
+impl Cascades {
+    /// Each shadow map covers a sphere around its slice, so that it keeps
+    /// its size as the camera turns, and moves in whole texels, so that
+    /// shadow edges stay put as the camera moves.
+    pub fn fit(orbiter: &Orbiter, sun: Vector3<f32>, resolution: u32) -> Self {
+        let (near, far) = orbiter.clip_planes();
+        let splits: [f32; CASCADES] = std::array::from_fn(|i| {
+            let t = (i + 1) as f32 / CASCADES as f32;
+            let even = near + (far - near) * t;
+            let logarithmic = near * (far / near).powf(t);
+            even + (logarithmic - even) * LOGARITHMIC_SPLITS
+        });
+
+        // Light space axes, with z towards the sun.
+        let z = sun.normalize();
+        let x = if z.y.abs() < 0.9 {
+            Vector3::unit_y().cross(z).normalize()
+        } else {
+            Vector3::unit_x().cross(z).normalize()
+        };
+        let y = z.cross(x);
+        let camera = orbiter.position();
+        let camera_along = |axis: Vector3<f32>| camera.dot(axis.cast().unwrap());
+
+        let mut matrices = [Matrix4::from_scale(1.0); CASCADES];
+        let mut texel_sizes = [0.0; CASCADES];
+        for i in 0..CASCADES {
+            let start = if i == 0 { near } else { splits[i - 1] };
+            let corners = orbiter.frustum_corners(start, splits[i]);
+            let center = corners.iter().sum::<Vector3<f32>>() / 8.0;
+            let radius = corners
+                .iter()
+                .map(|corner| (corner - center).magnitude())
+                .fold(0.0, f32::max);
+
+            // Snapped in world space rather than relative to the camera.
+            let texel = radius * 2.0 / resolution as f32;
+            let snap = |axis: Vector3<f32>| {
+                let world = camera_along(axis) + center.dot(axis) as f64;
+                ((world / texel as f64).round() * texel as f64 - camera_along(axis)) as f32
+            };
+            let (center_x, center_y) = (snap(x), snap(y));
+
+            // Reaching towards the sun for terrain outside the slice that
+            // casts shadows into it.
+            let top = center.dot(z) + radius + orbiter.shadow_reach();
+            let depth = top - center.dot(z) + radius;
+
+            let rows = [
+                (x / radius).extend(-center_x / radius),
+                (y / radius).extend(-center_y / radius),
+                (-z / depth).extend(top / depth),
+                Vector4::unit_w(),
+            ];
+            matrices[i] = Matrix4::from_cols(rows[0], rows[1], rows[2], rows[3]).transpose();
+            texel_sizes[i] = texel;
+        }
+
+        Self {
+            matrices,
+            splits,
+            texel_sizes,
+        }
+    }
+}
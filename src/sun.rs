@@ -0,0 +1,63 @@
+use cgmath::Vector3;
+use winit::event::VirtualKeyCode;
+
+use crate::{gen, input::Input, orbiter::FRAME_TIME, planet::Planet};
+
+/// Hours that go by per second while fast-forwarding or rewinding.
+const SKIP_SPEED: f32 = 3.0;
+
+pub struct Sun {
+    /// Past midnight at longitude 0, between 0 and 24.
+    hours: f32,
+    day_length: f32,
+    declination: f32,
+    ambient: f32,
+}
+
+impl Sun {
+    pub fn new(planet: &Planet) -> Self {
+        Self {
+            hours: planet.sun.time.rem_euclid(24.0),
+            day_length: planet.sun.day_length,
+            declination: planet.sun.declination,
+            ambient: planet.sun.ambient,
+        }
+    }
+
+    /// Takes on the settings of a planet, keeping the time of day.
+    pub fn reload(&mut self, planet: &Planet) {
+        self.day_length = planet.sun.day_length;
+        self.declination = planet.sun.declination;
+        self.ambient = planet.sun.ambient;
+    }
+
+    pub fn process(&mut self, input: &Input) {
+        if self.day_length > 0.0 {
+            self.hours += 24.0 * FRAME_TIME / self.day_length;
+        }
+
+        if input.is_key_down(VirtualKeyCode::RBracket) {
+            self.hours += SKIP_SPEED * FRAME_TIME;
+        }
+
+        if input.is_key_down(VirtualKeyCode::LBracket) {
+            self.hours -= SKIP_SPEED * FRAME_TIME;
+        }
+
+        self.hours = self.hours.rem_euclid(24.0);
+    }
+
+    pub fn hours(&self) -> f32 {
+        self.hours
+    }
+
+    /// Towards the sun, which is overhead at longitude 0 at noon and moves
+    /// west as the day goes on.
+    pub fn direction(&self) -> Vector3<f32> {
+        gen::direction(self.declination, (12.0 - self.hours) * 15.0)
+    }
+
+    pub fn ambient(&self) -> f32 {
+        self.ambient
+    }
+}
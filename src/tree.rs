@@ -2,12 +2,14 @@
 
 use std::rc::Rc;
 
-use cgmath::{vec2, Vector2, Zero};
+use cgmath::{vec2, MetricSpace, Vector2, Vector3, Zero};
+use rayon::prelude::*;
 
 use crate::{
-    gen::{self, PointSampler, QuadInfo, SCALE},
+    gen::{self, PointSampler, QuadInfo, Terrain},
     orbiter::Orbiter,
-    render::{Mesh, State},
+    render::{Instances, Mesh, State},
+    scatter::{Instance, Props},
 };
 
 pub struct Tree {
@@ -15,7 +17,7 @@ pub struct Tree {
 }
 
 impl Tree {
-    pub fn new(renderer: &State) -> Self {
+    pub fn new(terrain: &Terrain, renderer: &State) -> Self {
         Self {
             roots: Facing::all().map(|facing| RootQuad {
                 facing,
@@ -25,30 +27,96 @@ impl Tree {
                         scale: 1.0,
                         offset: Vector2::zero(),
                     },
+                    terrain,
                     renderer,
                 )),
             }),
         }
     }
 
-    pub fn collect_meshes(&self, meshes: &mut Vec<Rc<Mesh>>) {
+    pub fn collect_meshes(
+        &self,
+        meshes: &mut Vec<Rc<Mesh>>,
+        oceans: &mut Vec<Rc<Mesh>>,
+        props: &mut Vec<Rc<Instances>>,
+    ) {
         for root in &self.roots {
-            root.quad.collect_meshes(meshes);
+            root.quad.collect_meshes(meshes, oceans, props);
         }
     }
 
-    pub fn process(&mut self, orbiter: &Orbiter, renderer: &State) {
-        for root in &mut self.roots {
-            root.quad.process(
-                orbiter,
-                renderer,
-                ProcessInfo {
-                    facing: root.facing,
-                    offset: Vector2::zero(),
-                    scale: 1.0,
-                },
+    /// Props of the loaded quads within `distance` of `point`.
+    pub fn props_within(&self, point: Vector3<f64>, distance: f64) -> Vec<&Instance> {
+        let mut found = Vec::new();
+        for root in &self.roots {
+            root.quad.props_within(point, distance, &mut found);
+        }
+        found
+    }
+
+    /// Quads of the leaves as they are currently subdivided.
+    pub fn leaves(&self) -> Vec<gen::Info> {
+        let mut leaves = Vec::new();
+        for root in &self.roots {
+            root.quad
+                .leaves(ProcessInfo::root(root.facing), &mut leaves);
+        }
+        leaves
+    }
+
+    /// Quads of a tree subdivided `max_depth` levels below the cube faces,
+    /// either everywhere or only where a viewer at `point` would need it.
+    pub fn fixed_leaves(
+        terrain: &Terrain,
+        point: Option<Vector3<f64>>,
+        max_depth: u32,
+    ) -> Vec<gen::Info> {
+        fn visit(
+            terrain: &Terrain,
+            point: Option<Vector3<f64>>,
+            depth: u32,
+            info: ProcessInfo,
+            leaves: &mut Vec<gen::Info>,
+        ) {
+            let near = |point| {
+                let min_dist = terrain.lod_distance(info.scale).powi(2);
+                gen::quad_sampler(info.gen_info(), terrain).distance2(point) < min_dist
+            };
+
+            if depth > 0 && point.is_none_or(near) {
+                for info in info.children() {
+                    visit(terrain, point, depth - 1, info, leaves);
+                }
+            } else {
+                leaves.push(info.gen_info());
+            }
+        }
+
+        let mut leaves = Vec::new();
+        for facing in Facing::all() {
+            visit(
+                terrain,
+                point,
+                max_depth,
+                ProcessInfo::root(facing),
+                &mut leaves,
             );
         }
+        leaves
+    }
+
+    pub fn regenerate(&mut self, terrain: &Terrain, renderer: &State) {
+        for root in &mut self.roots {
+            root.quad
+                .regenerate(terrain, renderer, ProcessInfo::root(root.facing));
+        }
+    }
+
+    pub fn process(&mut self, orbiter: &Orbiter, terrain: &Terrain, renderer: &State) {
+        for root in &mut self.roots {
+            root.quad
+                .process(orbiter, terrain, renderer, ProcessInfo::root(root.facing));
+        }
     }
 }
 
@@ -59,115 +127,156 @@ struct RootQuad {
 
 enum Quad {
     Leaf(QuadInfo),
-    Branch(Box<[Self; 4]>, PointSampler),
+    Branch(Box<[Self; 4]>, PointSampler, Option<Props>),
 }
 
 impl Quad {
-    fn collect_meshes(&self, meshes: &mut Vec<Rc<Mesh>>) {
+    fn collect_meshes(
+        &self,
+        meshes: &mut Vec<Rc<Mesh>>,
+        oceans: &mut Vec<Rc<Mesh>>,
+        props: &mut Vec<Rc<Instances>>,
+    ) {
         match self {
-            Quad::Leaf(info) => meshes.push(info.mesh.clone()),
-            Quad::Branch(children, _) => {
+            Quad::Leaf(info) => {
+                meshes.push(info.mesh.clone());
+                oceans.extend(info.ocean.clone());
+                props.extend(info.props.as_ref().map(|props| props.buffer.clone()));
+            }
+            Quad::Branch(children, _, branch_props) => {
+                props.extend(branch_props.as_ref().map(|props| props.buffer.clone()));
                 for child in children.iter() {
-                    child.collect_meshes(meshes);
+                    child.collect_meshes(meshes, oceans, props);
                 }
             }
         }
     }
 
-    fn process(&mut self, orbiter: &Orbiter, renderer: &State, info: ProcessInfo) {
+    fn props_within<'a>(
+        &'a self,
+        point: Vector3<f64>,
+        distance: f64,
+        found: &mut Vec<&'a Instance>,
+    ) {
+        let props = match self {
+            Quad::Leaf(info) => &info.props,
+            Quad::Branch(children, _, props) => {
+                for child in children.iter() {
+                    child.props_within(point, distance, found);
+                }
+                props
+            }
+        };
+
+        if let Some(props) = props {
+            found.extend(
+                props
+                    .instances
+                    .iter()
+                    .filter(|instance| instance.position.distance(point) <= distance),
+            );
+        }
+    }
+
+    fn leaves(&self, info: ProcessInfo, leaves: &mut Vec<gen::Info>) {
+        match self {
+            Quad::Leaf(_) => leaves.push(info.gen_info()),
+            Quad::Branch(children, _, _) => {
+                for (child, info) in children.iter().zip(info.children()) {
+                    child.leaves(info, leaves);
+                }
+            }
+        }
+    }
+
+    fn process(
+        &mut self,
+        orbiter: &Orbiter,
+        terrain: &Terrain,
+        renderer: &State,
+        info: ProcessInfo,
+    ) {
         match self {
             Quad::Leaf(qinfo) => {
-                let min_dist = (info.scale * info.scale) * SCALE;
+                let min_dist = terrain.lod_distance(info.scale).powi(2);
                 let dist = qinfo.sampler.distance2(orbiter.position());
 
-                if dist < min_dist {
+                if dist < min_dist
+                    && terrain.worth_subdividing(qinfo.error, info.gen_info().spacing())
+                {
                     let mut sampler = PointSampler::empty();
                     std::mem::swap(&mut sampler, &mut qinfo.sampler);
-                    self.subdivide(renderer, info, sampler);
+                    let props = qinfo.props.take();
+                    self.subdivide(terrain, renderer, info, sampler, props);
                 }
             }
-            Quad::Branch(children, sampler) => {
-                let max_dist = (info.scale * info.scale) * SCALE * 1.5;
+            Quad::Branch(children, sampler, _) => {
+                let max_dist = terrain.lod_distance(info.scale).powi(2) * 1.5;
                 let dist = sampler.distance2(orbiter.position());
 
                 if dist > max_dist {
-                    self.collapse(renderer, info);
+                    self.collapse(terrain, renderer, info);
                     return;
                 }
 
-                let offsets = [
-                    vec2(-1.0, 1.0),
-                    vec2(1.0, 1.0),
-                    vec2(-1.0, -1.0),
-                    vec2(1.0, -1.0),
-                ];
-
-                for (child, offset) in children.iter_mut().zip(offsets.into_iter()) {
-                    child.process(
-                        orbiter,
-                        renderer,
-                        ProcessInfo {
-                            facing: info.facing,
-                            offset: info.offset + offset * info.scale * 0.5,
-                            scale: info.scale * 0.5,
-                        },
-                    );
+                for (child, info) in children.iter_mut().zip(info.children()) {
+                    child.process(orbiter, terrain, renderer, info);
+                }
+            }
+        }
+    }
+
+    fn regenerate(&mut self, terrain: &Terrain, renderer: &State, info: ProcessInfo) {
+        let gen_info = gen::Info {
+            facing: info.facing,
+            scale: info.scale,
+            offset: info.offset,
+        };
+
+        match self {
+            Quad::Leaf(qinfo) => *qinfo = gen::quad_mesh(gen_info, terrain, renderer),
+            Quad::Branch(children, sampler, props) => {
+                *sampler = gen::quad_sampler(gen_info, terrain);
+                *props = gen::upload_props(terrain.props(&gen_info), renderer);
+
+                for (child, info) in children.iter_mut().zip(info.children()) {
+                    child.regenerate(terrain, renderer, info);
                 }
             }
         }
     }
 
-    fn subdivide(&mut self, renderer: &State, info: ProcessInfo, sampler: PointSampler) {
+    fn subdivide(
+        &mut self,
+        terrain: &Terrain,
+        renderer: &State,
+        info: ProcessInfo,
+        sampler: PointSampler,
+        props: Option<Props>,
+    ) {
         if matches!(self, Self::Leaf(_)) {
-            let scale = info.scale * 0.5;
-            *self = Self::Branch(
-                Box::new([
-                    Quad::Leaf(gen::quad_mesh(
-                        gen::Info {
-                            facing: info.facing,
-                            scale,
-                            offset: info.offset + vec2(-1.0, 1.0) * scale,
-                        },
-                        renderer,
-                    )),
-                    Quad::Leaf(gen::quad_mesh(
-                        gen::Info {
-                            facing: info.facing,
-                            scale,
-                            offset: info.offset + vec2(1.0, 1.0) * scale,
-                        },
-                        renderer,
-                    )),
-                    Quad::Leaf(gen::quad_mesh(
-                        gen::Info {
-                            facing: info.facing,
-                            scale,
-                            offset: info.offset + vec2(-1.0, -1.0) * scale,
-                        },
-                        renderer,
-                    )),
-                    Quad::Leaf(gen::quad_mesh(
-                        gen::Info {
-                            facing: info.facing,
-                            scale,
-                            offset: info.offset + vec2(1.0, -1.0) * scale,
-                        },
-                        renderer,
-                    )),
-                ]),
-                sampler,
-            );
+            // Generate on worker threads, upload here.
+            let children = info.children().map(|info| info.gen_info());
+            let data: Vec<_> = children
+                .par_iter()
+                .map(|info| gen::quad_data(info, terrain))
+                .collect();
+
+            let mut data = data.into_iter();
+            let leaves = [(); 4].map(|_| Quad::Leaf(gen::upload(data.next().unwrap(), renderer)));
+            *self = Self::Branch(Box::new(leaves), sampler, props);
         }
     }
 
-    fn collapse(&mut self, renderer: &State, info: ProcessInfo) {
-        if matches!(self, Self::Branch(_, _)) {
+    fn collapse(&mut self, terrain: &Terrain, renderer: &State, info: ProcessInfo) {
+        if matches!(self, Self::Branch(..)) {
             *self = Self::Leaf(gen::quad_mesh(
                 gen::Info {
                     facing: info.facing,
                     scale: info.scale,
                     offset: info.offset,
                 },
+                terrain,
                 renderer,
             ))
         }
@@ -185,7 +294,7 @@ pub enum Facing {
 }
 
 impl Facing {
-    fn all() -> [Self; 6] {
+    pub fn all() -> [Self; 6] {
         [
             Self::North,
             Self::South,
@@ -202,3 +311,35 @@ struct ProcessInfo {
     offset: Vector2<f32>,
     scale: f32,
 }
+
+impl ProcessInfo {
+    fn root(facing: Facing) -> Self {
+        Self {
+            facing,
+            offset: Vector2::zero(),
+            scale: 1.0,
+        }
+    }
+
+    fn gen_info(&self) -> gen::Info {
+        gen::Info {
+            facing: self.facing,
+            scale: self.scale,
+            offset: self.offset,
+        }
+    }
+
+    fn children(&self) -> [Self; 4] {
+        [
+            vec2(-1.0, 1.0),
+            vec2(1.0, 1.0),
+            vec2(-1.0, -1.0),
+            vec2(1.0, -1.0),
+        ]
+        .map(|offset| Self {
+            facing: self.facing,
+            offset: self.offset + offset * self.scale * 0.5,
+            scale: self.scale * 0.5,
+        })
+    }
+}
@@ -0,0 +1,113 @@
+use cgmath::{vec3, Vector3, VectorSpace, Zero};
+
+/// Mesh of where a density field crosses zero, with one vertex per cell
+/// that the surface passes through and a quad around every edge it crosses.
+/// This is naive surface nets, the simplest form of dual contouring.
+pub struct Surface {
+    /// In grid coordinates, with corner (x, y, z) at (x, y, z).
+    pub points: Vec<Vector3<f32>>,
+    /// Vertices of the four cells around each crossed edge, in order around
+    /// it. In grid coordinates, they turn counterclockwise seen from the
+    /// empty end of the edge.
+    pub quads: Vec<[u32; 4]>,
+}
+
+/// Pairs of corners along the edges of a cell, with bit 0 of a corner for x,
+/// bit 1 for y and bit 2 for z.
+const EDGES: [[usize; 2]; 12] = [
+    [0, 1],
+    [2, 3],
+    [4, 5],
+    [6, 7],
+    [0, 2],
+    [1, 3],
+    [4, 6],
+    [5, 7],
+    [0, 4],
+    [1, 5],
+    [2, 6],
+    [3, 7],
+];
+
+/// `density` is positive inside, sampled on a cube of `size` corners along
+/// each edge, x fastest and z slowest.
+///
+/// Faces only come from edges that start below `size - 2` along their own
+/// axis and lie above 0 on the other two, so that grids laid one corner
+/// short of `size` apart, overlapping by two corners, mesh every edge
+/// exactly once between them.
+pub fn surface_nets(density: &[f32], size: usize) -> Surface {
+    let index = |x: usize, y: usize, z: usize| (z * size + y) * size + x;
+    let cells = size - 1;
+    let cell_index = |x: usize, y: usize, z: usize| (z * cells + y) * cells + x;
+
+    let mut cell_vertices = vec![u32::MAX; cells * cells * cells];
+    let mut points = Vec::new();
+
+    for z in 0..cells {
+        for y in 0..cells {
+            for x in 0..cells {
+                let corners: [f32; 8] = std::array::from_fn(|i| {
+                    density[index(x + (i & 1), y + (i >> 1 & 1), z + (i >> 2 & 1))]
+                });
+
+                let mut sum = Vector3::zero();
+                let mut crossings = 0;
+                for [a, b] in EDGES {
+                    let (da, db) = (corners[a], corners[b]);
+                    if (da > 0.0) != (db > 0.0) {
+                        sum += corner(a).lerp(corner(b), da / (da - db));
+                        crossings += 1;
+                    }
+                }
+
+                if crossings > 0 {
+                    cell_vertices[cell_index(x, y, z)] = points.len() as u32;
+                    points.push(vec3(x as f32, y as f32, z as f32) + sum / crossings as f32);
+                }
+            }
+        }
+    }
+
+    let mut quads = Vec::new();
+    for axis in 0..3 {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+
+        for z in 0..cells {
+            for y in 0..cells {
+                for x in 0..cells {
+                    let start = [x, y, z];
+                    if start[axis] >= size - 2 || start[u] == 0 || start[v] == 0 {
+                        continue;
+                    }
+
+                    let mut end = start;
+                    end[axis] += 1;
+                    let solid = density[index(x, y, z)] > 0.0;
+                    if solid == (density[index(end[0], end[1], end[2])] > 0.0) {
+                        continue;
+                    }
+
+                    let cell = |du: usize, dv: usize| {
+                        let mut c = start;
+                        c[u] -= du;
+                        c[v] -= dv;
+                        cell_vertices[cell_index(c[0], c[1], c[2])]
+                    };
+                    let quad = [cell(1, 1), cell(0, 1), cell(0, 0), cell(1, 0)];
+                    quads.push(if solid {
+                        quad
+                    } else {
+                        [quad[3], quad[2], quad[1], quad[0]]
+                    });
+                }
+            }
+        }
+    }
+
+    Surface { points, quads }
+}
+
+fn corner(i: usize) -> Vector3<f32> {
+    vec3((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2 & 1) as f32)
+}